@@ -0,0 +1,169 @@
+use realfft::{ComplexToReal, RealFftPlanner, RealToComplex};
+use rustfft::num_complex::Complex;
+use std::sync::Arc;
+
+use crate::{make_rng, process_sliced_chunk, NessError, NessRng};
+
+//the buffers one slice's ffts work in, made once and reused for every frame
+pub(crate) struct FftScratch {
+    pub(crate) part: Vec<f64>,
+    pub(crate) spectrum: Vec<Complex<f64>>,
+    pub(crate) spectrum_out: Vec<Complex<f64>>,
+    pub(crate) out_frame: Vec<f64>,
+    pub(crate) scratch: Vec<Complex<f64>>,
+    //the best correlating frame, the first half of the ness window and the crossfaded frame process_microframe makes
+    pub(crate) best_frame: Vec<f64>,
+    pub(crate) ness_window: Vec<f64>,
+    pub(crate) frame: Vec<f64>,
+}
+
+impl FftScratch {
+    pub(crate) fn new(fft: &dyn RealToComplex<f64>, ifft: &dyn ComplexToReal<f64>) -> FftScratch {
+        FftScratch {
+            part: fft.make_input_vec(),
+            spectrum: fft.make_output_vec(),
+            spectrum_out: ifft.make_input_vec(),
+            out_frame: ifft.make_output_vec(),
+            scratch: vec![Complex::new(0.0, 0.0); fft.get_scratch_len().max(ifft.get_scratch_len())],
+            best_frame: ifft.make_output_vec(),
+            ness_window: vec![0.0; fft.len() / 2],
+            frame: ifft.make_output_vec(),
+        }
+    }
+}
+
+//the settings every band and channel of a chunk share
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ChunkParams {
+    pub(crate) loops: usize,
+    pub(crate) num_ffts: usize,
+    pub(crate) max_win_size: usize,
+    pub(crate) win_size_divisor: usize,
+}
+
+//one spectral slice of the stretch - the windows, filters and fft plans every channel of the slice shares
+pub(crate) struct Band {
+    //the slice number, which numbers its random generators
+    pub(crate) slice: usize,
+    pub(crate) win_len: usize,
+    pub(crate) hop: f64,
+    pub(crate) filter_on: bool,
+    //the linear gain of the band's output, from the band split mode
+    pub(crate) gain: f64,
+    pub(crate) in_win: Vec<f64>,
+    //one filter for each subslice
+    pub(crate) filters: Vec<Vec<f64>>,
+    pub(crate) fft: Arc<dyn RealToComplex<f64>>,
+    pub(crate) ifft: Arc<dyn ComplexToReal<f64>>,
+}
+
+impl Band {
+    //the window length is the length of in_win, and the ffts of that length come from the planner
+    pub(crate) fn new(
+        slice: usize,
+        hop: f64,
+        filter_on: bool,
+        gain: f64,
+        in_win: Vec<f64>,
+        filters: Vec<Vec<f64>>,
+        planner: &mut RealFftPlanner<f64>,
+    ) -> Band {
+        let win_len = in_win.len();
+        Band {
+            slice,
+            win_len,
+            hop,
+            filter_on,
+            gain,
+            in_win,
+            filters,
+            fft: planner.plan_fft_forward(win_len),
+            ifft: planner.plan_fft_inverse(win_len),
+        }
+    }
+
+    //a rough count of the work one channel of the band does in a chunk
+    //every frame is an fft and loops * num_ffts iffts, and every ifft also walks its bins a few times to randomize and crossfade them
+    pub(crate) fn work(&self, params: ChunkParams) -> f64 {
+        let frames = params.max_win_size as f64 / (self.win_len / 2) as f64 / params.win_size_divisor as f64;
+        let win_len = self.win_len as f64;
+        let fft = win_len * win_len.log2();
+        frames * (fft + (params.loops * params.num_ffts) as f64 * (fft + BIN_WORK * win_len))
+    }
+}
+
+//roughly how many fft butterflies the polar conversions, random phase and crossfade of one bin cost
+//with this the per chunk work of every window size comes out close to even, which is what timing them shows
+const BIN_WORK: f64 = 100.0;
+
+//one channel of one band, which is the unit of work the thread pool balances
+//it owns everything that changes from chunk to chunk, so the units can run side by side without sharing anything mutable
+pub(crate) struct BandChannel {
+    //index into the bands and the channel it reads and writes
+    pub(crate) band: usize,
+    pub(crate) chan: usize,
+    //the last frames of every subslice
+    last_frame: Vec<f64>,
+    //the unit's output for the chunk, max_win_size frames, before the mix
+    pub(crate) out: Vec<f64>,
+    //the end of a frame that runs past the chunk and where the next chunk's first frame starts,
+    //for windows whose half doesn't divide max_win_size
+    carry: Vec<f64>,
+    first_out: usize,
+    rng: NessRng,
+    bufs: FftScratch,
+}
+
+impl BandChannel {
+    pub(crate) fn new(band_index: usize, band: &Band, chan: usize, max_win_size: usize, seed: Option<u64>) -> BandChannel {
+        BandChannel {
+            band: band_index,
+            chan,
+            last_frame: vec![0.0; band.win_len * 2],
+            out: vec![0.0; max_win_size],
+            carry: vec![0.0; band.win_len / 2],
+            first_out: 0,
+            rng: make_rng(seed, band.slice, chan),
+            bufs: FftScratch::new(band.fft.as_ref(), band.ifft.as_ref()),
+        }
+    }
+
+    //processes this channel of the chunk into out
+    //the last frames stay in the unit so they are there when we loop around to the next chunk
+    pub(crate) fn process(
+        &mut self,
+        band: &Band,
+        indata: &[f64],
+        chunk_point: usize,
+        params: ChunkParams,
+    ) -> Result<(), NessError> {
+        process_sliced_chunk(
+            indata,
+            chunk_point,
+            band.win_len,
+            band.filter_on,
+            &band.hop,
+            params.loops,
+            &band.in_win,
+            &band.filters,
+            &mut self.last_frame,
+            &mut self.out,
+            &mut self.carry,
+            &mut self.first_out,
+            &mut self.rng,
+            band.fft.as_ref(),
+            band.ifft.as_ref(),
+            &mut self.bufs,
+            params.num_ffts,
+            params.max_win_size,
+            params.win_size_divisor,
+        )?;
+        //the band's own gain is part of its output, so the stems add up to the mix
+        if band.gain != 1.0 {
+            for sample in self.out.iter_mut() {
+                *sample *= band.gain;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,418 @@
+use std::convert::TryFrom;
+
+use crate::validate::MAX_CORRELATION_FFTS;
+use crate::{BandSplitMode, CrossoverShape, NessError, StretchCurve, WindowKind};
+
+//the extreme algorithms change how many phase randomized frames are made per window and how the slice is subdivided
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExtremeMode {
+    //one randomized frame per window
+    #[default]
+    Standard,
+    //makes n randomized frames per window and keeps the one that best correlates with the previous frame
+    CorrelationSearch(usize),
+    //splits every slice into 4 subslices which are randomized separately
+    FourBand,
+    //splits every slice into 2 subslices, each picking the best of 3 randomized frames
+    TwoBand,
+}
+
+impl ExtremeMode {
+    //how many ifft loops (subslices) are run per window
+    pub(crate) fn loops(self) -> usize {
+        match self {
+            ExtremeMode::FourBand => 4,
+            ExtremeMode::TwoBand => 2,
+            _ => 1,
+        }
+    }
+
+    //how many randomized frames each subslice compares for correlation
+    pub(crate) fn num_ffts(self) -> usize {
+        match self {
+            ExtremeMode::CorrelationSearch(n) => n,
+            ExtremeMode::TwoBand => 3,
+            _ => 1,
+        }
+    }
+}
+
+//the numbering used by the command line tool: 0 standard, 1 ten correlations, 2 four bands, 3 two bands, >3 that many correlations
+impl TryFrom<usize> for ExtremeMode {
+    type Error = NessError;
+
+    fn try_from(extreme: usize) -> Result<ExtremeMode, NessError> {
+        match extreme {
+            0 => Ok(ExtremeMode::Standard),
+            1 => Ok(ExtremeMode::CorrelationSearch(10)),
+            2 => Ok(ExtremeMode::FourBand),
+            3 => Ok(ExtremeMode::TwoBand),
+            n if n <= MAX_CORRELATION_FFTS => Ok(ExtremeMode::CorrelationSearch(n)),
+            n => Err(NessError::invalid(
+                "extreme",
+                n,
+                format!("0 (standard), 1 (10 correlation candidates), 2 (four subslices), 3 (two subslices) or 4..={} (that many correlation candidates)", MAX_CORRELATION_FFTS),
+            )),
+        }
+    }
+}
+
+//the window size used when the file is not sliced (num_slices == 1), ie a classic paulstretch
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PaulstretchWindow {
+    //8192 samples
+    #[default]
+    Small,
+    //16384 samples
+    Medium,
+    //32768 samples
+    Large,
+}
+
+impl PaulstretchWindow {
+    //index into win_lens, which holds 2^(8+index)
+    pub(crate) fn index(self) -> usize {
+        match self {
+            PaulstretchWindow::Small => 5,
+            PaulstretchWindow::Medium => 6,
+            PaulstretchWindow::Large => 7,
+        }
+    }
+
+    //the window at 44.1K - other sample rates use the power of two closest to the same duration
+    pub fn win_len(self) -> usize {
+        256 << self.index()
+    }
+
+    pub fn win_len_at(self, sample_rate: u32) -> usize {
+        crate::layout::scale_window(self.win_len() as f64, sample_rate)
+    }
+}
+
+//the numbering used by the command line tool: 1, 2 or 3
+impl TryFrom<usize> for PaulstretchWindow {
+    type Error = NessError;
+
+    fn try_from(size: usize) -> Result<PaulstretchWindow, NessError> {
+        match size {
+            1 => Ok(PaulstretchWindow::Small),
+            2 => Ok(PaulstretchWindow::Medium),
+            3 => Ok(PaulstretchWindow::Large),
+            n => Err(NessError::invalid("paulstretch_win_size", n, "1 (8192), 2 (16384) or 3 (32768)")),
+        }
+    }
+}
+
+//whether process_file writes a file per band next to the mix, named after the band's window and frequency range
+//out.wav gets out_0_256_11025-22050Hz.wav, out_1_512_5512-11025Hz.wav and so on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StemMode {
+    //just the mix
+    #[default]
+    Off,
+    //the mix and the stems
+    WithMix,
+    //the stems and no mix
+    Only,
+}
+
+//a point in the input file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputPosition {
+    Seconds(f64),
+    Samples(usize),
+}
+
+impl InputPosition {
+    //the frame number at a sample rate
+    pub(crate) fn frames(self, sample_rate: u32) -> usize {
+        match self {
+            InputPosition::Seconds(seconds) => (seconds * sample_rate as f64).round() as usize,
+            InputPosition::Samples(samples) => samples,
+        }
+    }
+}
+
+//a length of output
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputLength {
+    Seconds(f64),
+    Samples(usize),
+}
+
+impl OutputLength {
+    //the number of frames at a sample rate
+    pub(crate) fn frames(self, sample_rate: u32) -> usize {
+        match self {
+            OutputLength::Seconds(seconds) => (seconds * sample_rate as f64).round() as usize,
+            OutputLength::Samples(samples) => samples,
+        }
+    }
+}
+
+//all of the settings for a stretch
+//NessConfig::new(100.0).extreme(ExtremeMode::TwoBand).num_slices(6)
+#[derive(Debug, Clone)]
+pub struct NessConfig {
+    pub(crate) dur_mult: f64,
+    //replaces dur_mult with a stretch that changes over the input
+    pub(crate) stretch_curve: Option<StretchCurve>,
+    //None stretches every slice by dur_mult
+    pub(crate) band_dur_mults: Option<Vec<f64>>,
+    pub(crate) extreme: ExtremeMode,
+    //None uses every slice the sample rate has room for
+    pub(crate) num_slices: Option<usize>,
+    //None uses octave bands, otherwise the slices are split at these frequencies
+    pub(crate) crossovers: Option<Vec<f64>>,
+    //None gives each slice a window that fits its highest frequency
+    pub(crate) win_lens: Option<Vec<usize>>,
+    //the input window of every slice, unless band_windows gives one per slice
+    pub(crate) window: WindowKind,
+    pub(crate) band_windows: Option<Vec<WindowKind>>,
+    pub(crate) band_split: BandSplitMode,
+    //the starting mix - NessStruct, NessStretcher and RealtimeStretcher can change it while they run
+    pub(crate) band_gains: Option<Vec<f64>>,
+    pub(crate) muted_bands: Vec<usize>,
+    pub(crate) soloed_bands: Vec<usize>,
+    //how the slices and subslices fade into each other
+    pub(crate) crossover_order: f64,
+    pub(crate) crossover_shape: CrossoverShape,
+    pub(crate) paulstretch_window: PaulstretchWindow,
+    pub(crate) win_size_divisor: usize,
+    pub(crate) num_output_blocks: Option<usize>,
+    //the part of the file process_file stretches, and whether the audio before it is read as context
+    pub(crate) region_start: Option<InputPosition>,
+    pub(crate) region_end: Option<InputPosition>,
+    pub(crate) pre_roll: bool,
+    //how long the output runs on after the stretched input, fading out
+    pub(crate) tail: Option<OutputLength>,
+    //replaces dur_mult with the stretch that makes this much output from the input process_file reads
+    pub(crate) target_duration: Option<OutputLength>,
+    pub(crate) stems: StemMode,
+    pub(crate) seed: Option<u64>,
+    pub(crate) num_threads: Option<usize>,
+    pub(crate) verbose: bool,
+}
+
+impl NessConfig {
+    //the defaults match the command line tool: all the slices the sample rate allows (9 at 44.1K), split at the crossovers, standard algorithm
+    pub fn new(dur_mult: f64) -> NessConfig {
+        NessConfig {
+            dur_mult,
+            stretch_curve: None,
+            band_dur_mults: None,
+            extreme: ExtremeMode::Standard,
+            num_slices: None,
+            crossovers: None,
+            win_lens: None,
+            window: WindowKind::Paul,
+            band_windows: None,
+            band_split: BandSplitMode::Crossover,
+            band_gains: None,
+            muted_bands: vec![],
+            soloed_bands: vec![],
+            crossover_order: 64.0,
+            crossover_shape: CrossoverShape::LinkwitzRiley,
+            paulstretch_window: PaulstretchWindow::Small,
+            win_size_divisor: 1,
+            num_output_blocks: None,
+            region_start: None,
+            region_end: None,
+            pre_roll: false,
+            tail: None,
+            target_duration: None,
+            stems: StemMode::Off,
+            seed: None,
+            num_threads: None,
+            verbose: false,
+        }
+    }
+
+    pub fn dur_mult(mut self, dur_mult: f64) -> NessConfig {
+        self.dur_mult = dur_mult;
+        self
+    }
+
+    //a stretch that follows the input time, eg StretchCurve::breakpoints(vec![(0.0, 2.0), (60.0, 200.0)])
+    //dur_mult is only used to pick which bands follow the curve when band_dur_mults is set - the ones at dur_mult do
+    //only NessStretcher and process_file follow the curve - RealtimeStretcher can't
+    pub fn stretch_curve(mut self, stretch_curve: StretchCurve) -> NessConfig {
+        self.stretch_curve = Some(stretch_curve);
+        self
+    }
+
+    //a stretch for each slice in use, from the highest band to the lowest, eg 8 on top and 100 on the bass
+    //each band reads through the input at its own speed, so they line up at the start and drift apart after it
+    //the output is still the input's length times dur_mult, so bands that stretch less run out of input and go quiet
+    //only NessStretcher and process_file can read ahead for the faster bands - RealtimeStretcher can't
+    pub fn band_dur_mults(mut self, band_dur_mults: Vec<f64>) -> NessConfig {
+        self.band_dur_mults = Some(band_dur_mults);
+        self
+    }
+
+    pub fn extreme(mut self, extreme: ExtremeMode) -> NessConfig {
+        self.extreme = extreme;
+        self
+    }
+
+    //how many spectral slices to split the sound into - 1 is a classic paulstretch
+    //slices are taken from the top of the spectrum down, and the last one covers everything below it
+    //asking for more than the sample rate allows uses them all
+    pub fn num_slices(mut self, num_slices: usize) -> NessConfig {
+        self.num_slices = Some(num_slices);
+        self
+    }
+
+    //the frequencies in Hz where one slice hands over to the next, eg vec![80.0, 200.0, 500.0, 1200.0, 3000.0, 8000.0]
+    //there is one more slice than crossovers, and each slice gets a window that fits its highest frequency
+    //without crossovers the slices are octaves, from 11025Hz down to 86Hz at 44.1K
+    pub fn crossovers(mut self, crossovers: Vec<f64>) -> NessConfig {
+        self.crossovers = Some(crossovers);
+        self
+    }
+
+    //the fft size of each slice in use, from the highest band to the lowest, eg vec![512, 2048, 4096, 24000]
+    //there has to be one window per slice, and every window has to be even - they don't have to be powers of two
+    //the largest window sets the size of a chunk
+    //with num_slices(1) the one window replaces the paulstretch window
+    pub fn win_lens(mut self, win_lens: Vec<usize>) -> NessConfig {
+        self.win_lens = Some(win_lens);
+        self
+    }
+
+    //the window every slice multiplies its input frames by
+    pub fn window(mut self, window: WindowKind) -> NessConfig {
+        self.window = window;
+        self
+    }
+
+    //a window for each slice in use, from the highest band to the lowest, replacing window
+    pub fn band_windows(mut self, band_windows: Vec<WindowKind>) -> NessConfig {
+        self.band_windows = Some(band_windows);
+        self
+    }
+
+    //how the slices share the spectrum - crossovers, overlapping crossovers or full range layers
+    //a single slice always covers the full spectrum at unity gain
+    pub fn band_split(mut self, band_split: BandSplitMode) -> NessConfig {
+        self.band_split = band_split;
+        self
+    }
+
+    //the command line tool's switch: on is BandSplitMode::Crossover, off is BandSplitMode::Layered(0.0)
+    pub fn filter(mut self, filter: bool) -> NessConfig {
+        self.band_split = if filter { BandSplitMode::Crossover } else { BandSplitMode::Layered(0.0) };
+        self
+    }
+
+    //the gain in dB of each slice in use, from the highest band to the lowest
+    pub fn band_gains(mut self, band_gains: Vec<f64>) -> NessConfig {
+        self.band_gains = Some(band_gains);
+        self
+    }
+
+    //slices that are left out of the mix, numbered from 0 for the highest band
+    pub fn muted_bands(mut self, muted_bands: Vec<usize>) -> NessConfig {
+        self.muted_bands = muted_bands;
+        self
+    }
+
+    //when any slice is soloed only the soloed slices are mixed, numbered from 0 for the highest band
+    pub fn soloed_bands(mut self, soloed_bands: Vec<usize>) -> NessConfig {
+        self.soloed_bands = soloed_bands;
+        self
+    }
+
+    //how steep the crossovers between slices and between the extreme subslices are - 64 is close to a brick wall
+    //lower orders overlap neighbouring slices more, which softens the seams between window sizes
+    pub fn crossover_order(mut self, crossover_order: f64) -> NessConfig {
+        self.crossover_order = crossover_order;
+        self
+    }
+
+    pub fn crossover_shape(mut self, crossover_shape: CrossoverShape) -> NessConfig {
+        self.crossover_shape = crossover_shape;
+        self
+    }
+
+    pub fn paulstretch_window(mut self, paulstretch_window: PaulstretchWindow) -> NessConfig {
+        self.paulstretch_window = paulstretch_window;
+        self
+    }
+
+    //only processes 1/win_size_divisor of the frames in each chunk
+    pub fn win_size_divisor(mut self, win_size_divisor: usize) -> NessConfig {
+        self.win_size_divisor = win_size_divisor;
+        self
+    }
+
+    //stops process_file after this many max_win_size blocks of output
+    pub fn num_output_blocks(mut self, num_output_blocks: usize) -> NessConfig {
+        self.num_output_blocks = Some(num_output_blocks);
+        self
+    }
+
+    //where process_file starts stretching - the first frames are centered on it
+    //without it the whole file is stretched and the first frames are centered on its first frame
+    pub fn region_start(mut self, region_start: InputPosition) -> NessConfig {
+        self.region_start = Some(region_start);
+        self
+    }
+
+    //where process_file stops reading - the output is the length of the region times the stretch
+    pub fn region_end(mut self, region_end: InputPosition) -> NessConfig {
+        self.region_end = Some(region_end);
+        self
+    }
+
+    //when on, the first windows of a region read the audio before region_start instead of silence
+    pub fn pre_roll(mut self, pre_roll: bool) -> NessConfig {
+        self.pre_roll = pre_roll;
+        self
+    }
+
+    //lets the sound run on past the end of the stretched input and fade out over this long, so the last windows
+    //decay instead of stopping - without it the output is round(input length * dur_mult) frames
+    pub fn tail(mut self, tail: OutputLength) -> NessConfig {
+        self.tail = Some(tail);
+        self
+    }
+
+    //makes process_file write exactly this much output, with dur_mult worked out from the length of the input or region
+    //the last block is cut to length and faded out - it can't be used with num_output_blocks or tail
+    //with a stretch curve the curve keeps its stretches and the output is just cut or padded to the length
+    pub fn target_duration(mut self, target_duration: OutputLength) -> NessConfig {
+        self.target_duration = Some(target_duration);
+        self
+    }
+
+    //writes each band of process_file's output to its own file, before the band gains, mutes and solos
+    pub fn stems(mut self, stems: StemMode) -> NessConfig {
+        self.stems = stems;
+        self
+    }
+
+    //seeds the random phases, so the same seed, input and settings always give the same output
+    //without a seed every render is different
+    pub fn seed(mut self, seed: u64) -> NessConfig {
+        self.seed = Some(seed);
+        self
+    }
+
+    //caps how many worker threads process the slices
+    //without a cap there is one thread per cpu (or RAYON_NUM_THREADS if it is set)
+    pub fn num_threads(mut self, num_threads: usize) -> NessConfig {
+        self.num_threads = Some(num_threads);
+        self
+    }
+
+    pub fn verbose(mut self, verbose: bool) -> NessConfig {
+        self.verbose = verbose;
+        self
+    }
+
+    //checks every setting and reports the first one that is out of range
+    pub fn validate(&self) -> Result<(), NessError> {
+        crate::validate::validate_config(self)
+    }
+}
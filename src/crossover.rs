@@ -0,0 +1,94 @@
+//how the slices share the spectrum
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum BandSplitMode {
+    //every slice is filtered to the range between its crossovers
+    #[default]
+    Crossover,
+    //every slice reaches this many octaves past its crossovers, so neighbouring window sizes overlap and blend
+    Overlapping(f64),
+    //every slice passes the full spectrum at this gain in dB and the slices are stacked on top of each other,
+    //which smears every frequency across all of the window sizes
+    //the slices add by power, so with 9 of them about -9.5dB keeps the level of a crossover split
+    Layered(f64),
+}
+
+impl BandSplitMode {
+    //whether the slices are filtered at all
+    pub(crate) fn filtered(self) -> bool {
+        !matches!(self, BandSplitMode::Layered(_))
+    }
+
+    //how far past its crossovers a slice reaches, as a frequency ratio
+    pub(crate) fn overlap(self) -> f64 {
+        match self {
+            BandSplitMode::Overlapping(octaves) => f64::powf(2.0, octaves),
+            _ => 1.0,
+        }
+    }
+
+    //the linear gain of every slice
+    pub(crate) fn gain(self) -> f64 {
+        match self {
+            BandSplitMode::Layered(gain_db) => db_to_gain(gain_db),
+            _ => 1.0,
+        }
+    }
+}
+
+pub(crate) fn db_to_gain(gain_db: f64) -> f64 {
+    f64::powf(10.0, gain_db / 20.0)
+}
+
+//the shape of the fade between neighbouring slices (and between the subslices of the extreme modes)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CrossoverShape {
+    //1 / (1 + (f/fc)^order) - at the default order of 64 it is close to a brick wall
+    #[default]
+    LinkwitzRiley,
+    //a half cosine fade, the two sides add up to 1
+    RaisedCosine,
+    //a straight fade, the two sides add up to 1
+    Linear,
+    //a quarter sine and cosine fade whose squares add up to 1
+    //the slices have random phases, so they add by power and this keeps the level flat through the crossover
+    PowerComplementary,
+}
+
+//the fades other than linkwitz-riley go from one slice to the next over this many octaves divided by the order,
+//centered on the crossover - 1/16 of an octave at an order of 64, an octave at 4
+//that is close to the width of a linkwitz-riley fade of the same order
+const TRANSITION_OCTAVES: f64 = 4.0;
+
+impl CrossoverShape {
+    //the gain below a crossover, where ratio is the frequency over the crossover frequency
+    pub(crate) fn low_pass(self, ratio: f64, order: f64) -> f64 {
+        match self {
+            CrossoverShape::LinkwitzRiley => 1.0 / (1.0 + (f64::powf(ratio, order))),
+            CrossoverShape::PowerComplementary => (transition(ratio, order) * std::f64::consts::FRAC_PI_2).cos(),
+            _ => 1.0 - self.rise(transition(ratio, order)),
+        }
+    }
+
+    //the gain above a crossover
+    pub(crate) fn high_pass(self, ratio: f64, order: f64) -> f64 {
+        match self {
+            CrossoverShape::LinkwitzRiley => 1.0 - (1.0 / (1.0 + (f64::powf(ratio, order)))),
+            CrossoverShape::PowerComplementary => (transition(ratio, order) * std::f64::consts::FRAC_PI_2).sin(),
+            _ => self.rise(transition(ratio, order)),
+        }
+    }
+
+    //the amplitude complementary fades, 0 at the start of the transition and 1 at the end
+    fn rise(self, position: f64) -> f64 {
+        match self {
+            CrossoverShape::RaisedCosine => 0.5 - 0.5 * (position * std::f64::consts::PI).cos(),
+            _ => position,
+        }
+    }
+}
+
+//how far through the transition a frequency is, from 0 below it to 1 above it
+fn transition(ratio: f64, order: f64) -> f64 {
+    let width = TRANSITION_OCTAVES / order;
+    (0.5 + ratio.log2() / width).clamp(0.0, 1.0)
+}
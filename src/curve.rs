@@ -0,0 +1,77 @@
+use std::fmt;
+use std::sync::Arc;
+
+//a stretch that changes over the input, so a piece can start at 2x and drift to 200x
+//the stretch is looked up at the read position of every chunk, and the frames carry on from where the last chunk left them,
+//so the rate changes in steps of max_win_size output frames without any jumps in the sound
+#[derive(Clone)]
+pub enum StretchCurve {
+    //(input time in seconds, stretch) points, joined by straight lines and held flat before the first and after the last
+    Breakpoints(Vec<(f64, f64)>),
+    //the stretch at an input time in seconds
+    Function(Arc<dyn Fn(f64) -> f64 + Send + Sync>),
+}
+
+impl StretchCurve {
+    pub fn breakpoints(points: Vec<(f64, f64)>) -> StretchCurve {
+        StretchCurve::Breakpoints(points)
+    }
+
+    pub fn function(function: impl Fn(f64) -> f64 + Send + Sync + 'static) -> StretchCurve {
+        StretchCurve::Function(Arc::new(function))
+    }
+
+    //the stretch at an input time in seconds
+    pub(crate) fn dur_mult_at(&self, seconds: f64) -> f64 {
+        match self {
+            StretchCurve::Breakpoints(points) => {
+                let next = points.iter().position(|(time, _)| *time > seconds).unwrap_or(points.len());
+                if next == 0 {
+                    return points[0].1;
+                }
+                if next == points.len() {
+                    return points[points.len() - 1].1;
+                }
+                let (time0, dur_mult0) = points[next - 1];
+                let (time1, dur_mult1) = points[next];
+                dur_mult0 + (dur_mult1 - dur_mult0) * (seconds - time0) / (time1 - time0)
+            }
+            StretchCurve::Function(function) => function(seconds),
+        }
+    }
+}
+
+impl fmt::Debug for StretchCurve {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StretchCurve::Breakpoints(points) => f.debug_tuple("Breakpoints").field(points).finish(),
+            StretchCurve::Function(_) => f.write_str("Function(..)"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn breakpoints_are_joined_by_straight_lines() {
+        let curve = StretchCurve::breakpoints(vec![(1.0, 2.0), (3.0, 6.0), (4.0, 5.0)]);
+        assert_eq!(curve.dur_mult_at(1.0), 2.0);
+        assert_eq!(curve.dur_mult_at(1.5), 3.0);
+        assert_eq!(curve.dur_mult_at(3.0), 6.0);
+        assert_eq!(curve.dur_mult_at(3.25), 5.75);
+    }
+
+    #[test]
+    fn breakpoints_hold_flat_outside_the_curve() {
+        let curve = StretchCurve::breakpoints(vec![(1.0, 2.0), (3.0, 6.0)]);
+        assert_eq!(curve.dur_mult_at(0.0), 2.0);
+        assert_eq!(curve.dur_mult_at(-1.0), 2.0);
+        assert_eq!(curve.dur_mult_at(3.5), 6.0);
+        assert_eq!(curve.dur_mult_at(100.0), 6.0);
+        let single = StretchCurve::breakpoints(vec![(2.0, 8.0)]);
+        assert_eq!(single.dur_mult_at(0.0), 8.0);
+        assert_eq!(single.dur_mult_at(5.0), 8.0);
+    }
+}
@@ -0,0 +1,90 @@
+use std::fmt;
+
+//everything that can go wrong while reading, stretching or writing a file
+#[derive(Debug)]
+pub enum NessError {
+    //the file could not be opened or read from disk
+    Io(std::io::Error),
+    //the file opened, but a sample or header could not be decoded
+    Decode(hound::Error),
+    //the file is valid, but not something the stretch can work with
+    UnsupportedFormat(String),
+    //one of the stretch parameters is out of range
+    InvalidParameter {
+        name: &'static str,
+        value: String,
+        allowed: String,
+    },
+    //the fft was handed buffers of the wrong size
+    Fft(realfft::FftError),
+    //the output file could not be created, written or finalized
+    Write(hound::Error),
+    //the worker threads for the slices could not be started
+    ThreadPool(rayon::ThreadPoolBuildError),
+}
+
+impl NessError {
+    //wav read errors are split into io, format and decode problems
+    pub(crate) fn from_read(err: hound::Error) -> NessError {
+        match err {
+            hound::Error::IoError(e) => NessError::Io(e),
+            hound::Error::Unsupported => NessError::UnsupportedFormat("unsupported wav encoding".to_string()),
+            hound::Error::TooWide => NessError::UnsupportedFormat("sample width is not supported".to_string()),
+            e => NessError::Decode(e),
+        }
+    }
+
+    pub(crate) fn invalid(name: &'static str, value: impl fmt::Debug, allowed: impl Into<String>) -> NessError {
+        NessError::InvalidParameter {
+            name,
+            value: format!("{:?}", value),
+            allowed: allowed.into(),
+        }
+    }
+}
+
+impl fmt::Display for NessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NessError::Io(e) => write!(f, "i/o error: {}", e),
+            NessError::Decode(e) => write!(f, "could not decode input: {}", e),
+            NessError::UnsupportedFormat(msg) => write!(f, "unsupported format: {}", msg),
+            NessError::InvalidParameter { name, value, allowed } => {
+                write!(f, "invalid {}: {} (allowed: {})", name, value, allowed)
+            }
+            NessError::Fft(e) => write!(f, "fft error: {}", e),
+            NessError::Write(e) => write!(f, "could not write output: {}", e),
+            NessError::ThreadPool(e) => write!(f, "could not start worker threads: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for NessError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            NessError::Io(e) => Some(e),
+            NessError::Decode(e) | NessError::Write(e) => Some(e),
+            NessError::Fft(e) => Some(e),
+            NessError::ThreadPool(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for NessError {
+    fn from(err: std::io::Error) -> NessError {
+        NessError::Io(err)
+    }
+}
+
+impl From<realfft::FftError> for NessError {
+    fn from(err: realfft::FftError) -> NessError {
+        NessError::Fft(err)
+    }
+}
+
+impl From<rayon::ThreadPoolBuildError> for NessError {
+    fn from(err: rayon::ThreadPoolBuildError) -> NessError {
+        NessError::ThreadPool(err)
+    }
+}
@@ -0,0 +1,82 @@
+//how fast the read position moves, from 1 (the stretch) to 0 (frozen on one moment of the input)
+//the speed glides to its target one step per chunk, so going in and out of a freeze slows down and speeds up the sound
+//instead of stopping it dead
+pub(crate) struct SpeedRamp {
+    pub(crate) speed: f64,
+    target: f64,
+    step: f64,
+}
+
+impl SpeedRamp {
+    pub(crate) fn new() -> SpeedRamp {
+        SpeedRamp { speed: 1.0, target: 1.0, step: 0.0 }
+    }
+
+    //heads for target over ramp_chunks chunks - 0 gets there on the next chunk
+    pub(crate) fn ramp_to(&mut self, target: f64, ramp_chunks: usize) {
+        self.target = target;
+        self.step = (target - self.speed).abs() / ramp_chunks.max(1) as f64;
+    }
+
+    //moves the speed on for the next chunk and returns whether it changed
+    pub(crate) fn next(&mut self) -> bool {
+        if self.speed == self.target {
+            return false;
+        }
+        if (self.target - self.speed).abs() <= self.step {
+            self.speed = self.target;
+        } else if self.target > self.speed {
+            self.speed += self.step;
+        } else {
+            self.speed -= self.step;
+        }
+        true
+    }
+
+    //jumps straight to a speed and stays there
+    pub(crate) fn set(&mut self, speed: f64) {
+        self.speed = speed;
+        self.target = speed;
+        self.step = 0.0;
+    }
+
+    pub(crate) fn is_frozen(&self) -> bool {
+        self.speed == 0.0 && self.target == 0.0
+    }
+}
+
+//a freeze on a chosen point of the input
+//the read position goes on at the stretch until it is time to glide, then slows down in even steps so it stops
+//exactly on the point - over m chunks the speeds are v, v*(m-1)/m .. v/m, which read v*(m+1)/2 chunks of input
+pub(crate) struct FreezeTarget {
+    //the chunk point to hold, in the stretcher's input
+    pub(crate) point: f64,
+    glide_chunks: usize,
+    gliding: bool,
+}
+
+impl FreezeTarget {
+    pub(crate) fn new(point: f64, glide_chunks: usize) -> FreezeTarget {
+        FreezeTarget { point, glide_chunks, gliding: false }
+    }
+
+    //whether the next chunk jumps straight to the point
+    pub(crate) fn jumps(&self) -> bool {
+        self.glide_chunks == 0
+    }
+
+    //the speed of the next chunk, from how far the read position has left to go and how far a chunk reads at the
+    //stretch - None once it is there
+    pub(crate) fn next_speed(&mut self, distance: f64, advance: f64) -> Option<f64> {
+        if self.glide_chunks == 0 || distance <= 0.0 {
+            return None;
+        }
+        let speed = 2.0 * distance / ((self.glide_chunks + 1) as f64 * advance);
+        if !self.gliding && speed > 1.0 {
+            return Some(1.0);
+        }
+        self.gliding = true;
+        self.glide_chunks -= 1;
+        Some(speed)
+    }
+}
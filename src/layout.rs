@@ -0,0 +1,144 @@
+use crate::validate::{MAX_WIN_SIZE, MIN_WIN_SIZE};
+use crate::{NessConfig, NessError};
+
+//the slices are designed at 44.1K, where they use windows of 256 to 65536 samples
+//at other sample rates every slice keeps the same window duration and frequency range
+pub(crate) const REFERENCE_RATE: f64 = 44100.0;
+//at the reference rate the top slice covers 11025 to 22050 and every slice below it covers the octave below,
+//down to the lowest slice, which covers everything below 86Hz
+const REFERENCE_TOP: f64 = 22050.0;
+const REFERENCE_CROSSOVERS: i32 = 8;
+//the window of a slice holds this many cycles of its highest frequency - 256 samples for the top octave at 44.1K
+const CYCLES_PER_WINDOW: f64 = 128.0;
+
+//the window and frequency range of one slice at a particular sample rate
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct SliceLayout {
+    pub(crate) win_len: usize,
+    pub(crate) low_hz: f64,
+    pub(crate) high_hz: f64,
+}
+
+impl SliceLayout {
+    //the fft bin of a frequency in this slice's window
+    pub(crate) fn bin(&self, hz: f64, sample_rate: u32) -> f64 {
+        hz * self.win_len as f64 / sample_rate as f64
+    }
+}
+
+//the power of two closest to a length in samples, measured on a log scale
+pub(crate) fn closest_power_of_two(samples: f64) -> usize {
+    let exponent = samples.max(1.0).log2().round() as u32;
+    1usize << exponent
+}
+
+//the window a duration given in samples at the reference rate has at sample_rate
+pub(crate) fn scale_window(reference_len: f64, sample_rate: u32) -> usize {
+    closest_power_of_two(reference_len * sample_rate as f64 / REFERENCE_RATE)
+}
+
+//the octave crossovers used when none are given, highest first
+//extra ones above 22050Hz are added at high sample rates and the top ones are dropped at low sample rates
+//a crossover is only kept if at least half an octave is left above it before nyquist
+pub(crate) fn default_crossovers(sample_rate: u32) -> Vec<f64> {
+    let nyquist = sample_rate as f64 / 2.0;
+    let mut crossovers = vec![];
+    for octave in (-REFERENCE_CROSSOVERS..REFERENCE_CROSSOVERS).rev() {
+        let crossover = REFERENCE_TOP / f64::powi(2.0, octave + 1);
+        if crossover >= nyquist / f64::sqrt(2.0) {
+            break;
+        }
+        crossovers.push(crossover);
+    }
+    crossovers.reverse();
+    crossovers
+}
+
+//the slices a config asks for at a sample rate
+pub(crate) fn config_layout(config: &NessConfig, sample_rate: u32) -> Result<Vec<SliceLayout>, NessError> {
+    let crossovers = match &config.crossovers {
+        Some(crossovers) => {
+            let mut crossovers = crossovers.clone();
+            crossovers.sort_by(|a, b| b.total_cmp(a));
+            crossovers
+        }
+        None => default_crossovers(sample_rate),
+    };
+    slice_layout(&crossovers, sample_rate)
+}
+
+//the number of output frames in a chunk
+//it is the largest window in use - the lowest slice's unless the config gives its own windows
+pub(crate) fn chunk_size(config: &NessConfig, layout: &[SliceLayout]) -> usize {
+    match &config.win_lens {
+        Some(win_lens) => win_lens.iter().copied().max().unwrap_or(0),
+        None => layout[layout.len() - 1].win_len,
+    }
+}
+
+//the slices between the crossovers, from the highest band to the lowest
+//the top slice reaches up to nyquist and the lowest one all the way down
+//crossovers has to be sorted highest first
+pub(crate) fn slice_layout(crossovers: &[f64], sample_rate: u32) -> Result<Vec<SliceLayout>, NessError> {
+    let nyquist = sample_rate as f64 / 2.0;
+    if let Some(crossover) = crossovers.iter().find(|crossover| **crossover >= nyquist) {
+        return Err(NessError::invalid("crossovers", crossover, format!("frequencies below nyquist ({}Hz)", nyquist)));
+    }
+
+    let mut slices = vec![];
+    for iter in 0..=crossovers.len() {
+        let high_hz = if iter == 0 { nyquist } else { crossovers[iter - 1] };
+        let low_hz = if iter == crossovers.len() { 0.0 } else { crossovers[iter] };
+        slices.push(SliceLayout {
+            win_len: closest_power_of_two(CYCLES_PER_WINDOW * sample_rate as f64 / high_hz),
+            low_hz,
+            high_hz,
+        });
+    }
+
+    //the lowest slice has the largest window, and that is the size of a chunk
+    let max_win_size = slices[slices.len() - 1].win_len;
+    if max_win_size < MIN_WIN_SIZE {
+        return Err(NessError::UnsupportedFormat(format!("sample rate {} is too low to stretch", sample_rate)));
+    }
+    if max_win_size > MAX_WIN_SIZE {
+        return Err(NessError::invalid(
+            "crossovers",
+            crossovers[crossovers.len() - 1],
+            format!("a lowest crossover that needs a window of at most {} samples", MAX_WIN_SIZE),
+        ));
+    }
+    Ok(slices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn win_lens(sample_rate: u32) -> Vec<usize> {
+        config_layout(&NessConfig::new(2.0), sample_rate).unwrap().iter().map(|slice| slice.win_len).collect()
+    }
+
+    #[test]
+    fn the_number_of_slices_follows_the_sample_rate() {
+        for (sample_rate, num_slices) in [(44100, 9), (48000, 9), (192000, 11)] {
+            let layout = config_layout(&NessConfig::new(2.0), sample_rate).unwrap();
+            assert_eq!(layout.len(), num_slices, "at {}", sample_rate);
+            assert_eq!(layout[0].high_hz, sample_rate as f64 / 2.0);
+            assert_eq!(layout[num_slices - 1].low_hz, 0.0);
+        }
+    }
+
+    #[test]
+    fn windows_keep_their_duration_at_odd_sample_rates() {
+        //the top slices are dropped at 11025, so its windows run from the 256 of the top slice to a quarter of 44.1K's largest
+        assert_eq!(win_lens(11025), [256, 512, 1024, 2048, 4096, 8192, 16384]);
+        //37800 rounds each window to the closest power of two of 128 cycles - 438.9 samples for 11025Hz is 512
+        assert_eq!(win_lens(37800), [256, 512, 1024, 2048, 4096, 8192, 16384, 32768, 65536]);
+    }
+
+    #[test]
+    fn a_sample_rate_without_room_for_a_window_is_refused() {
+        assert!(matches!(config_layout(&NessConfig::new(2.0), 0), Err(NessError::UnsupportedFormat(_))));
+    }
+}
@@ -1,1030 +1,740 @@
-use crossbeam_utils::thread;
-use rand::Rng;
-use realfft::RealFftPlanner;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use rayon::prelude::*;
+use realfft::{ComplexToReal, RealFftPlanner, RealToComplex};
 use rustfft::num_complex::Complex;
-use std::f64::consts::PI;
 use std::time::SystemTime;
 
-const MAX_SLICES: usize = 10;
+mod band;
+mod config;
+mod crossover;
+mod curve;
+mod error;
+mod freeze;
+mod layout;
+mod microframe;
+mod mix;
+mod realtime;
+mod stretcher;
+mod validate;
+mod window;
+
+use band::{Band, BandChannel, ChunkParams, FftScratch};
+use freeze::SpeedRamp;
+use microframe::{crossfade, fill_ness_window, inverse_fft, randomize_bins, CorrelationSearch};
+use mix::BandMix;
+
+pub use config::{ExtremeMode, InputPosition, NessConfig, OutputLength, PaulstretchWindow, StemMode};
+pub use crossover::{BandSplitMode, CrossoverShape};
+pub use curve::StretchCurve;
+pub use error::NessError;
+pub use realtime::RealtimeStretcher;
+pub use stretcher::NessStretcher;
+pub use window::WindowKind;
+
+//the random phase generator - chacha's output is fixed across versions, so a seed always renders the same take
+pub(crate) type NessRng = ChaCha8Rng;
+
+//every slice and channel gets its own generator so the output doesn't depend on which thread runs first
+pub(crate) fn make_rng(seed: Option<u64>, slice: usize, chan: usize) -> NessRng {
+    match seed {
+        Some(seed) => {
+            let mut rng = NessRng::seed_from_u64(seed);
+            rng.set_stream(((slice as u64) << 32) | chan as u64);
+            rng
+        }
+        None => NessRng::from_entropy(),
+    }
+}
 
 pub struct NessStruct {
+    pub sample_rate: u32,
     pub max_win_size: usize,
     win_size_divisor: usize,
     pub num_channels: usize,
-    pub out_frame_size: usize,
     pub num_slices: usize,
+    //the window size of every slice in use, from the highest band to the lowest, and its hop
     pub win_lens: Vec<usize>,
     pub hops: Vec<f64>,
+    //the frequency range of every slice in use in Hz, low then high
+    pub freq_ranges: Vec<(f64, f64)>,
     pub loops: usize,
-    in_wins: Vec<Vec<f64>>,
-    filters: Vec<Vec<Vec<f64>>>,
-    filter_on: usize,
-    extreme: usize,
-    paulstretch_win_size: usize,
-    last_frames: Vec<Vec<f64>>,
+    num_ffts: usize,
+    //one band per slice that is processed
+    bands: Vec<Band>,
+    //every (slice, channel) pair, ordered from the most work to the least so the thread pool starts the longest ones first
+    units: Vec<BandChannel>,
+    mix: BandMix,
+    //scales every hop, for freezing
+    speed: SpeedRamp,
+    //the worker threads the units run on, started with the first chunk and kept until the struct is dropped
+    num_threads: Option<usize>,
+    pool: Option<rayon::ThreadPool>,
+    //the input of a chunk, 2*max_win_size frames per channel read from chunk * max_win_size / dur_mult
     pub in_chunk: Vec<Vec<f64>>,
+    //the stretch of every slice in use - a band whose stretch isn't dur_mult reads its own input from
+    //chunk * max_win_size / its stretch, in band_in_chunks[band], and the other bands' entries are empty
+    pub band_dur_mults: Vec<f64>,
+    pub band_in_chunks: Vec<Vec<Vec<f64>>>,
     pub stored_chunk: Vec<Vec<f64>>,
 }
 
 impl NessStruct {
-    pub fn new(dur_mult: f64, max_win_size: usize, win_size_divisor: usize, num_channels: usize, num_slices: usize, filter_on: usize, mut extreme: usize, paulstretch_win_size: usize, verbosity: usize) -> NessStruct {
+    //the windows and crossovers come from the sample rate and the config's crossovers, so the stretch sounds the same at any rate
+    #[allow(clippy::needless_range_loop)]
+    pub fn new(config: &NessConfig, sample_rate: u32, num_channels: usize) -> Result<NessStruct, NessError> {
+        config.validate()?;
+        let layout = layout::config_layout(config, sample_rate)?;
+        let num_slices = config.num_slices.unwrap_or(layout.len());
+        validate::validate_layout(config, &layout, sample_rate, num_slices, num_channels)?;
+        
+        let dur_mult = config.dur_mult;
+        let extreme = config.extreme;
         
-        //this is the size the frames that are calculated by process_sliced_chunk ==
-        //[processed audio(max_win_size)][last_frame1][last_frame2][last_frame3][last_frame4] (for the 4 possible subslices of the slice)
-        let out_frame_size: usize = max_win_size * 3;
+        //single slice mode is a classic paulstretch with one window covering everything, unfiltered
+        //its random generators are numbered by the paulstretch window so a seed sounds the same whatever the window
+        let mut slices: Vec<(usize, layout::SliceLayout)> = if num_slices == 1 {
+            let win_len = config.paulstretch_window.win_len_at(sample_rate);
+            vec![(config.paulstretch_window.index(), layout::SliceLayout { win_len, low_hz: 0.0, high_hz: sample_rate as f64 / 2.0 })]
+        } else {
+            let mut slices: Vec<(usize, layout::SliceLayout)> = layout[..num_slices].iter().copied().enumerate().collect();
+            //the lowest slice in use reaches all the way down, even when the layout has slices below it
+            slices[num_slices - 1].1.low_hz = 0.0;
+            slices
+        };
+        //windows from the config replace the ones that fit each slice's frequency range
+        if let Some(custom_lens) = &config.win_lens {
+            for (slice, win_len) in slices.iter_mut().zip(custom_lens) {
+                slice.1.win_len = *win_len;
+            }
+        }
         
-        //256 is always the smallest win_lens, 131072 always the largest (the extras just don't get used)
+        //the largest window is the size of a chunk
+        let max_win_size = layout::chunk_size(config, &layout);
         let mut win_lens = vec![0_usize; 0];
         let mut hops = vec![0_f64; 0];
-        for iter in 0..MAX_SLICES {
-            let size = u32::pow(2, 8 + iter as u32);
+        let mut freq_ranges = vec![(0.0_f64, 0.0_f64); 0];
+        //every band stretches by dur_mult unless the config gives each band its own
+        let band_dur_mults = config.band_dur_mults.clone().unwrap_or_else(|| vec![dur_mult; slices.len()]);
+        for (iter, (_, slice)) in slices.iter().enumerate() {
+            freq_ranges.push((slice.low_hz, slice.high_hz));
             //pushes the window sizes into the vector
-            win_lens.push(size as usize);
+            win_lens.push(slice.win_len);
             //pushes the hopsize for each slice into the vector
-            hops.push((size as f64 / 2.0) / dur_mult);
+            hops.push((slice.win_len as f64 / 2.0) / band_dur_mults[iter]);
         }
         
+        let band_split = if num_slices > 1 { config.band_split } else { BandSplitMode::Layered(0.0) };
+        let filter_on = band_split.filtered();
+        let nyquist = sample_rate as f64 / 2.0;
         
-        //creates a vector of fft cutoff bins based on the number of spectral slices
+        //creates a vector of fft cutoff bins from the frequency range of each slice
         //the extreme versions can split those cuttoffs into 2 and 4 more subslices
-        let cut_max = max_win_size as f64 / 512.0;
-        let mut cut_offs = vec![vec![0.0_f64; 0]; MAX_SLICES];
-        for iter in 0..MAX_SLICES {
-            let cutty: Vec<f64>;
+        let mut cut_offs = vec![vec![0.0_f64; 0]; slices.len()];
+        for (iter, (_, slice)) in slices.iter().enumerate() {
+            //overlapping slices reach past their crossovers on both sides
+            let low_cut = slice.bin(slice.low_hz / band_split.overlap(), sample_rate);
+            let hi_cut = slice.bin((slice.high_hz * band_split.overlap()).min(nyquist), sample_rate);
             //add low_cut, then hi_cut
-            if iter == (num_slices - 1) {
-                cutty = vec![
+            //the lowest slice reaches all the way down
+            cut_offs[iter] = if iter == slices.len() - 1 {
+                vec![
                 1.0,
-                cut_max / 4.0,
-                cut_max / 2.0,
-                3.0 * cut_max / 4.0,
-                cut_max,
-                ];
-            } else {
-                cutty = vec![
-                cut_max / 2.0,
-                5.0 * cut_max / 8.0,
-                3.0 * cut_max / 4.0,
-                7.0 * cut_max / 8.0,
-                cut_max,
-                ];
-            }
-            if num_slices == 1 {
-                cut_offs = vec![cutty; MAX_SLICES];
+                hi_cut / 4.0,
+                hi_cut / 2.0,
+                3.0 * hi_cut / 4.0,
+                hi_cut,
+                ]
             } else {
-                cut_offs[iter] = cutty;
-            }
-        }
-
-        let mut last_frames = vec![vec![0.0; 0]; 10];
-        for iter in 0..10 {
-            last_frames[iter] = vec![0.0; win_lens[iter] * 2 * num_channels];
+                vec![
+                low_cut,
+                low_cut + (hi_cut - low_cut) / 4.0,
+                low_cut + (hi_cut - low_cut) / 2.0,
+                low_cut + 3.0 * (hi_cut - low_cut) / 4.0,
+                hi_cut,
+                ]
+            };
         }
-
-        let in_chunk = vec![vec![0.0; max_win_size*2]; num_channels];
-        let stored_chunk = vec![vec![0.0; max_win_size*2]; num_channels];
         
         //this reconfigures the number of ifft loops and arrangement of the cut_offs depending on the extreme algorithm setting
-        let mut loops = 1;
-        match extreme {
-            2 => {
-                loops = 4;
-            }
-            3 => {
-                loops = 2;
-            }
-            _ => {
-
-            }
-        }
-        for iter in 0..num_slices {
+        let loops = extreme.loops();
+        for iter in 0..cut_offs.len() {
 
             match extreme {
-                0 => {
-                    cut_offs[iter][1] = cut_offs[iter][4];
-                }
-                1 => {
-                    cut_offs[iter][1] = cut_offs[iter][4];
-                }
-                3 => {
+                ExtremeMode::TwoBand => {
                     cut_offs[iter][1] = cut_offs[iter][2];
                     cut_offs[iter][2] = cut_offs[iter][4];
                 }
                 _ => {
                     cut_offs[iter][1] = cut_offs[iter][4];
-                    if extreme < 4 {
-                        extreme = 0
-                    }
                 }
             }
         }
 
-        if verbosity == 1 {
-            println!("window sizes {:?}", &win_lens[0..num_slices]);
+        if config.verbose {
+            println!("window sizes {:?}", slices.iter().map(|(_, slice)| slice.win_len).collect::<Vec<usize>>());
+            println!("frequency ranges {:?}", freq_ranges);
             println!("spectral cut offs {:?}", cut_offs);
         }
 
-       // let mut filters = vec![vec![0.0_f64; 0]; num_slices];
-
-        let mut filters: Vec<Vec<Vec<f64>>> = vec![vec![vec![0.0; 0]; 4]; MAX_SLICES];
-        let mut in_wins: Vec<Vec<f64>> = vec![vec![0.0; 0]; MAX_SLICES];
-        //let mut ness_wins: Vec<Vec<Vec<f64>>> = vec![vec![vec![0.0; 0]; 101]; num_slices];
-
-        for iter in 0..MAX_SLICES {
+        //the ffts are planned once here, not on every frame
+        let mut real_planner = RealFftPlanner::<f64>::new();
+        let mut bands = Vec::with_capacity(slices.len());
+        for (iter, (slice, layout)) in slices.iter().enumerate() {
+            let win_len = layout.win_len;
+            let mut filters: Vec<Vec<f64>> = vec![vec![0.0; 0]; loops];
             for iter2 in 0..loops {
-                let filt_win = make_lr_bp_window(win_lens[iter]/2 + 1, cut_offs[iter][iter2], cut_offs[iter][iter2 + 1], 64.0);
-                filters[iter][iter2] = filt_win;
+                //makes the crossover window at the cuttoff points - linquitz-riley unless the config asks for another shape
+                filters[iter2] = make_lr_bp_window(win_len/2 + 1, cut_offs[iter][iter2], cut_offs[iter][iter2 + 1], config.crossover_order, config.crossover_shape);
             }
-            // for iter2 in 0..=100 {
-            //     let ness_win = make_ness_window(win_lens[iter], iter as f64/100.0);
-            //     ness_wins[iter][iter2] = ness_win;
-            // }
+            //the input window is the same for every band unless the config gives one per band
+            let window = config.band_windows.as_ref().map_or(config.window, |windows| windows[iter]);
+            bands.push(Band::new(*slice, hops[iter], filter_on, band_split.gain(), window.make(win_len), filters, &mut real_planner));
+        }
 
-            in_wins[iter] = make_paul_window(win_lens[iter]); //uses a paul window on the input
+        //each channel of each band is processed on its own
+        //the small windows make many more frames per chunk than the big ones, so the heaviest units go first
+        let num_ffts = extreme.num_ffts();
+        let mut units = Vec::with_capacity(bands.len() * num_channels);
+        for (iter, band) in bands.iter().enumerate() {
+            for chan in 0..num_channels {
+                units.push(BandChannel::new(iter, band, chan, max_win_size, config.seed));
+            }
         }
+        let params = ChunkParams { loops, num_ffts, max_win_size, win_size_divisor: config.win_size_divisor };
+        let work = |unit: &BandChannel| bands[unit.band].work(params);
+        units.sort_by(|a, b| work(b).total_cmp(&work(a)));
+
+        let in_chunk = vec![vec![0.0; max_win_size*2]; num_channels];
+        let stored_chunk = vec![vec![0.0; max_win_size*2]; num_channels];
+        let band_in_chunks = band_dur_mults.iter()
+            .map(|band_dur_mult| if *band_dur_mult == dur_mult { vec![] } else { vec![vec![0.0; max_win_size*2]; num_channels] })
+            .collect();
 
-        NessStruct {max_win_size, win_size_divisor, num_channels, out_frame_size, num_slices, win_lens, hops, loops, in_wins, //ness_wins, 
-            filters, filter_on, extreme, paulstretch_win_size,
-            //last_frame0,last_frame1,last_frame2,last_frame3,last_frame4,last_frame5,last_frame6,last_frame7,last_frame8,last_frame9,
-            last_frames,
-            in_chunk, stored_chunk
+        Ok(NessStruct {sample_rate, max_win_size, win_size_divisor: config.win_size_divisor, num_channels, num_slices, win_lens, hops, freq_ranges, loops,
+            num_ffts, mix: BandMix::new(config, bands.len()), speed: SpeedRamp::new(), bands, units, num_threads: config.num_threads, pool: None,
+            in_chunk, band_dur_mults, band_in_chunks, stored_chunk
+        })
+    }
+
+    //changes the stretch from the next chunk on, for the bands that don't have their own stretch
+    //the caller moves the chunk point forward by max_win_size * chunk_speed() / dur_mult for the chunk after it
+    pub fn set_dur_mult(&mut self, dur_mult: f64) -> Result<(), NessError> {
+        if !dur_mult.is_finite() || dur_mult < 1.0 {
+            return Err(NessError::invalid("dur_mult", dur_mult, "a finite number >= 1.0"));
         }
+        for iter in 0..self.bands.len() {
+            if self.band_in_chunks[iter].is_empty() {
+                self.band_dur_mults[iter] = dur_mult;
+            }
+        }
+        self.update_hops();
+        Ok(())
     }
-}
 
+    //stops the read position, gliding to a halt over ramp_chunks chunks
+    //once frozen every chunk is new random phase frames of the same moment, centered on in_chunk[max_win_size / 2],
+    //for as long as chunks are processed - the caller holds the chunk point still, as NessStretcher does
+    //the caller also picks the moment, by where it fills in_chunk from - NessStretcher::freeze_at holds a chosen sample
+    pub fn freeze(&mut self, ramp_chunks: usize) {
+        self.speed.ramp_to(0.0, ramp_chunks);
+    }
 
-pub fn process_file(file_name: String, dur_mult: f64, extreme: usize, mut num_slices: usize, num_output_blocks: usize, verbosity: usize, filter_on: usize, paulstretch_win_size: usize, out_file: String) {
-    //reading the sound file using hound
-    //only works with wav files - would be great to replace this with something that works with other formats
-    
-    let mut sound_file = hound::WavReader::open(file_name).unwrap();
-    let mut intemp = vec![0.0; 0];
-    
-    //loads the sound file into intemp
-    //checks to see the format of the sound file and converts all input (float, int16, int24, etc) to f64
-    if sound_file.spec().sample_format == hound::SampleFormat::Float {
-        intemp.append(
-            &mut sound_file
-            .samples::<f32>()
-            .map(|x| x.unwrap() as f64)
-            .collect::<Vec<_>>(),
-        );
-    } else {
-        intemp.append(
-            &mut sound_file
-            .samples::<i32>()
-            .map(|x| x.unwrap() as f64)
-            .collect::<Vec<_>>(),
-        );
-        let bits = sound_file.spec().bits_per_sample;
-        for iter in 0..intemp.len() {
-            intemp[iter] = intemp[iter] / (f64::powf(2.0, bits as f64));
+    //starts the read position again, gliding back up to the stretch over ramp_chunks chunks
+    pub fn unfreeze(&mut self, ramp_chunks: usize) {
+        self.speed.ramp_to(1.0, ramp_chunks);
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.speed.is_frozen()
+    }
+
+    //sets the read speed of the next chunks straight away, with no glide - 0 is frozen
+    pub(crate) fn set_speed(&mut self, speed: f64) {
+        self.speed.set(speed);
+        self.update_hops();
+    }
+
+    //how fast the last chunk read through its input, from 1 at the stretch to 0 frozen
+    //the chunk points have to move forward this much slower
+    pub fn chunk_speed(&self) -> f64 {
+        self.speed.speed
+    }
+
+    //the hop of every band from its stretch and the freeze speed
+    fn update_hops(&mut self) {
+        for (iter, band) in self.bands.iter_mut().enumerate() {
+            band.hop = (band.win_len as f64 / 2.0) / self.band_dur_mults[iter] * self.speed.speed;
+            self.hops[iter] = band.hop;
         }
-    };
-    
-    
-    //if the sample rate is 88.2K or above, the largest window will be 131072, otherwise 65536
-    //let mut sr_mult = 1;
-    let sample_rate = sound_file.spec().sample_rate;
-    
-    // if sample_rate >= 88200 {
-    //     sr_mult = 2;
-    // } else if sample_rate >= 176400 {
-    //     sr_mult = 4;
-    // }
+    }
+
+    //one band's part of the last chunk, before the gain, mute and solo of the mix
+    //bands are numbered from 0 for the highest, like win_lens
+    pub fn band_chunk(&self, band: usize, chan: usize) -> Option<&[f64]> {
+        self.units.iter().find(|unit| unit.band == band && unit.chan == chan).map(|unit| &unit.out[..])
+    }
+
+    //the mix of the bands, numbered from 0 for the highest - changes ramp in across the next process_chunk
+    pub fn set_band_gain(&mut self, band: usize, gain_db: f64) -> Result<(), NessError> {
+        self.mix.set_gain(band, gain_db)
+    }
+
+    pub fn set_band_mute(&mut self, band: usize, mute: bool) -> Result<(), NessError> {
+        self.mix.set_mute(band, mute)
+    }
+
+    pub fn set_band_solo(&mut self, band: usize, solo: bool) -> Result<(), NessError> {
+        self.mix.set_solo(band, solo)
+    }
+}
+
+
+//limits num_slices to what the sample rate allows
+//the top slices are dropped at low sample rates and extra ones above 22050Hz are added at high rates - 9 at 44.1K and 48K, 10 at 96K, 11 at 192K
+pub(crate) fn config_for_sample_rate(config: &NessConfig, sample_rate: u32) -> Result<NessConfig, NessError> {
+    config.validate()?;
+    let layout = layout::config_layout(config, sample_rate)?;
     
-    let max_win_size: usize = 65536 * (sample_rate as usize/44100);
-    // let mut max_win_size = 16384 * (sample_rate as usize/44100);
-    // if num_slices > 1 {
-    //     max_win_size = usize::pow(2, 7+num_slices as u32) * (sample_rate as usize/44100);
-    // }
+    let mut config = config.clone();
+    let num_slices = config.num_slices.unwrap_or(layout.len()).min(layout.len());
+    config.num_slices = Some(num_slices);
     
-    if verbosity == 1 {
-        println!("Max Window Size: {}", max_win_size);
+    if config.verbose {
+        println!("Max Window Size: {}", layout::chunk_size(&config, &layout));
         if num_slices == 1 {
-            println!("PaulStretch window size: {:?}", i32::pow(2,8+(paulstretch_win_size+4) as u32));
+            println!("PaulStretch window size: {:?}", config.paulstretch_window.win_len_at(sample_rate));
         }
-    }
-    
-    //chunks the interleved file into chunks of size channels
-    //then transposes the interleaved file into individual vectors for each channel
-    let chunked: Vec<Vec<f64>> = intemp
-    .chunks(sound_file.spec().channels as usize)
-    .map(|x| x.to_vec())
-    .collect();
-    let mut channels = transpose(chunked);
-    
-    //then creates output vectors for each channel as well
-    //let out_channels: Vec<Vec<f32>> = vec![vec![0.0_f32; 0]; sound_file.spec().channels as usize];
-    
-    let now = SystemTime::now();
-    
-    //the higher sample rates can have 10 slices
-    if sample_rate < 88200 && num_slices > 9 {
-        num_slices = MAX_SLICES - 1;
-    } else if sample_rate >= 88200 && num_slices > 9 {
-        num_slices = MAX_SLICES;
-    }
-    if verbosity == 1 {
         println!("The audio file will be sliced into {} slices", num_slices);
     }
+    Ok(config)
+}
+
+
+pub fn process_file(file_name: String, config: &NessConfig, out_file: String) -> Result<(), NessError> {
+    //catch bad settings before touching the file
+    config.validate()?;
+    let verbose = config.verbose;
     
-    let num_channels = channels.len();
-    
-    let mut ness_struct: NessStruct = NessStruct::new(dur_mult, max_win_size, 1, num_channels, num_slices, filter_on, extreme, paulstretch_win_size, verbosity);
+    //reading the sound file using hound
+    //only works with wav files - would be great to replace this with something that works with other formats
     
-    let mut indata = vec![vec![0.0_f64; 0]; num_channels]; //empty array
-    let in_size = channels[0].len(); //max_win_size + 
+    let mut sound_file = hound::WavReader::open(file_name).map_err(NessError::from_read)?;
+    let spec = sound_file.spec();
+    let num_channels = spec.channels as usize;
+    let sample_rate = spec.sample_rate;
     
-    //let frames_to_add = 2 * max_win_size - (in_size % max_win_size);
-    //println!("framestoadd {}", frames_to_add);
-    for i in 0..num_channels {
-        indata[i].append(&mut channels[i]); //adds the channel data to indata
-        //indata[i].append(&mut vec![0.0_f64; frames_to_add]); //adds 0s at the end of indata so that the vector size is divisible by max_win_size
-        // println!("indatasize {}", indata[i].len());
+    if num_channels == 0 || sound_file.duration() == 0 {
+        return Err(NessError::UnsupportedFormat("the file contains no audio".to_string()));
     }
     
-
-    let mut num_chunks = (in_size as f64 / max_win_size as f64 * dur_mult) as usize;
-    if num_output_blocks > 0 {
-        num_chunks = num_output_blocks;
+    //only the region is stretched, and with pre-roll the half window before it is read as context
+    let (region_start, region_end) = validate::validate_region(config, sample_rate, sound_file.duration() as usize)?;
+    
+    //a target duration sets the stretch from the length of the region
+    let mut config = config.clone();
+    let target_len = config.target_duration.map(|target_duration| target_duration.frames(sample_rate));
+    if let Some(target_len) = target_len {
+        if config.stretch_curve.is_none() {
+            config.dur_mult = validate::target_dur_mult(&config, region_end - region_start, target_len)?;
+        }
     }
-
-    let mut chunk_points = vec![0; num_chunks];
     
-    for iter in 0..num_chunks {
-        chunk_points[iter] = ((iter * max_win_size) as f64 / dur_mult) as usize;
+    let mut stretcher = NessStretcher::new(&config, sample_rate, num_channels)?;
+    let max_win_size = stretcher.max_win_size();
+    if let Some(target_len) = target_len {
+        stretcher.set_output_len(target_len);
     }
     
     //hound is the wav reader and writer
-    let spec = hound::WavSpec {
+    let out_spec = hound::WavSpec {
         channels: num_channels as u16,
-        sample_rate: sample_rate,
+        sample_rate,
         bits_per_sample: 32,
         sample_format: hound::SampleFormat::Float,
     };
     
-    let mut writer = hound::WavWriter::create(out_file, spec).unwrap();
+    let mut writer = match config.stems {
+        StemMode::Only => None,
+        _ => Some(hound::WavWriter::create(&out_file, out_spec).map_err(NessError::Write)?),
+    };
+    //one writer per band when stems are asked for
+    let mut stem_writers = vec![];
+    if config.stems != StemMode::Off {
+        for (band, (win_len, freq_range)) in stretcher.win_lens().iter().zip(stretcher.freq_ranges().iter()).enumerate() {
+            let stem_file = stem_file_name(&out_file, band, *win_len, *freq_range);
+            stem_writers.push(hound::WavWriter::create(stem_file, out_spec).map_err(NessError::Write)?);
+        }
+    }
+    
+    let now = SystemTime::now();
     
-    let start_iter = 0;
+    let read_start = if config.region_start.is_some() && config.pre_roll { region_start.saturating_sub(max_win_size / 2) } else { region_start };
+    sound_file.seek(read_start as u32).map_err(NessError::Io)?;
     
-    //go through the chunk_points, making max_win_size chunks of audio
-    for iter in 0..num_chunks {
-        //only process the chunk if iter>
-        if iter >= start_iter && (iter < (num_chunks - start_iter)) {
-
-            if iter % 25 == 0 && verbosity == 1 {
-                println!("chunk {} of {}", iter, num_chunks)
+    //the file is read max_win_size frames at a time and pushed into the stretcher
+    //checks to see the format of the sound file and converts all input (float, int16, int24, etc) to floats
+    let int_scale = 1.0 / f64::powf(2.0, spec.bits_per_sample as f64);
+    let mut samples: Box<dyn Iterator<Item = Result<f32, hound::Error>>> = if spec.sample_format == hound::SampleFormat::Float {
+        Box::new(sound_file.samples::<f32>())
+    } else {
+        Box::new(sound_file.samples::<i32>().map(move |x| x.map(|x| (x as f64 * int_scale) as f32)))
+    };
+    
+    let mut in_block = vec![vec![0.0_f32; max_win_size]; num_channels];
+    let mut out_block = vec![vec![0.0_f32; max_win_size]; num_channels];
+    let mut stem_blocks = vec![vec![vec![0.0_f32; max_win_size]; num_channels]; stem_writers.len()];
+    //the stretch starts with its context, which is silence without pre-roll, so the first frames are centered on
+    //region_start, or on the first frame of the file, and the last input is heard at the end of the stretched length
+    let frames = read_block(&mut samples, &mut in_block, region_start - read_start)?;
+    let block: Vec<&[f32]> = in_block.iter().map(|chan| &chan[..frames]).collect();
+    stretcher.push_context(&block)?;
+    let mut remaining = region_end - region_start;
+    let mut reading = true;
+    while !stretcher.is_done() {
+        if reading {
+            let frames = read_block(&mut samples, &mut in_block, max_win_size.min(remaining))?;
+            remaining -= frames;
+            let block: Vec<&[f32]> = in_block.iter().map(|chan| &chan[..frames]).collect();
+            stretcher.push(&block)?;
+            if frames < max_win_size || remaining == 0 {
+                stretcher.finish();
+                reading = false;
             }
-
-            for chan in 0..num_channels {
-                for sample in 0..(max_win_size*2){
-                    let point = chunk_points[iter]+sample;
-                    if point < indata[0].len() {
-                        ness_struct.in_chunk[chan][sample] = indata[chan][point];
-                    } else {
-                        ness_struct.in_chunk[chan][sample] = 0.0f64;
-                    }
-                    
-                }
+        }
+        
+        loop {
+            let mut block: Vec<&mut [f32]> = out_block.iter_mut().map(|chan| &mut chan[..]).collect();
+            let frames = if stem_writers.is_empty() {
+                stretcher.pull(&mut block)?
+            } else {
+                let mut stems: Vec<Vec<&mut [f32]>> = stem_blocks.iter_mut().map(|band| band.iter_mut().map(|chan| &mut chan[..]).collect()).collect();
+                stretcher.pull_stems(&mut block, &mut stems)?
+            };
+            if let Some(writer) = &mut writer {
+                write_frames(writer, &out_block, frames)?;
             }
-            
-            ness_struct.stored_chunk = process_chunk(&mut ness_struct);
-            for samp in 0..(max_win_size) {
-                (0..channels.len()).for_each(|chan| {
-                    writer.write_sample(ness_struct.stored_chunk[chan][samp] as f32).unwrap();
-                });
+            for (stem_writer, stem_block) in stem_writers.iter_mut().zip(stem_blocks.iter()) {
+                write_frames(stem_writer, stem_block, frames)?;
+            }
+            if frames < max_win_size {
+                break;
             }
         }
-        
     }
     
-    //close the output file
-    writer.finalize().unwrap();
-    if verbosity == 1 {
+    //close the output files
+    if let Some(writer) = writer {
+        writer.finalize().map_err(NessError::Write)?;
+    }
+    for stem_writer in stem_writers {
+        stem_writer.finalize().map_err(NessError::Write)?;
+    }
+    
+    if verbose {
         println!("{:?}", now.elapsed())
     };
-    
+    Ok(())
+}
+
+
+//deinterleaves up to max_frames frames of the file into in_block and returns how many there were
+fn read_block(samples: &mut dyn Iterator<Item = Result<f32, hound::Error>>, in_block: &mut [Vec<f32>], max_frames: usize) -> Result<usize, NessError> {
+    let mut frames = 0;
+    'read: while frames < max_frames {
+        for chan in in_block.iter_mut() {
+            match samples.next() {
+                Some(sample) => chan[frames] = sample.map_err(NessError::from_read)?,
+                None => break 'read,
+            }
+        }
+        frames += 1;
+    }
+    Ok(frames)
 }
 
+//interleaves frames of a block into a wav file
+fn write_frames<W: std::io::Write + std::io::Seek>(writer: &mut hound::WavWriter<W>, block: &[Vec<f32>], frames: usize) -> Result<(), NessError> {
+    for samp in 0..frames {
+        for chan in block.iter() {
+            writer.write_sample(chan[samp]).map_err(NessError::Write)?;
+        }
+    }
+    Ok(())
+}
 
+//the stem of band number band goes next to out_file, eg out.wav gives out_3_2048_1378-2756Hz.wav
+fn stem_file_name(out_file: &str, band: usize, win_len: usize, freq_range: (f64, f64)) -> String {
+    let path = std::path::Path::new(out_file);
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default();
+    let extension = path.extension().map(|extension| extension.to_string_lossy()).unwrap_or_else(|| "wav".into());
+    let file_name = format!("{}_{}_{}_{:.0}-{:.0}Hz.{}", stem, band, win_len, freq_range.0, freq_range.1, extension);
+    path.with_file_name(file_name).to_string_lossy().into_owned()
+}
+
+//processes the chunk in ness_struct.in_chunk and returns max_win_size frames of stretched audio per channel
+#[allow(clippy::needless_return)]
 pub fn process_chunk(
     ness_struct: &mut NessStruct,
     
-) -> Vec<Vec<f64>> {
+) -> Result<Vec<Vec<f64>>, NessError> {
+    let mut out_data: Vec<Vec<f64>> = vec![vec![0.0; ness_struct.max_win_size]; ness_struct.num_channels];
+    process_chunk_into(ness_struct, &mut out_data)?;
+    return Ok(out_data);
+}
+
+//the same as process_chunk, but writes into out_data instead of allocating
+//out_data needs a vector per channel with at least max_win_size frames - only the first max_win_size are written
+pub fn process_chunk_into(
+    ness_struct: &mut NessStruct,
+    out_data: &mut [Vec<f64>],
+) -> Result<(), NessError> {
+    
+    //in_chunk is public, so make sure it still has the shape the slices expect before reading from it
+    if ness_struct.in_chunk.len() != ness_struct.num_channels {
+        return Err(NessError::invalid("in_chunk channels", ness_struct.in_chunk.len(), ness_struct.num_channels.to_string()));
+    }
+    if let Some(chan) = ness_struct.in_chunk.iter().find(|chan| chan.len() != ness_struct.max_win_size * 2) {
+        return Err(NessError::invalid("in_chunk length", chan.len(), (ness_struct.max_win_size * 2).to_string()));
+    }
+    for band_in_chunk in ness_struct.band_in_chunks.iter().filter(|band_in_chunk| !band_in_chunk.is_empty()) {
+        if band_in_chunk.len() != ness_struct.num_channels {
+            return Err(NessError::invalid("band_in_chunks channels", band_in_chunk.len(), ness_struct.num_channels.to_string()));
+        }
+        if let Some(chan) = band_in_chunk.iter().find(|chan| chan.len() != ness_struct.max_win_size * 2) {
+            return Err(NessError::invalid("band_in_chunks length", chan.len(), (ness_struct.max_win_size * 2).to_string()));
+        }
+    }
+    if out_data.len() != ness_struct.num_channels {
+        return Err(NessError::invalid("out_data channels", out_data.len(), ness_struct.num_channels.to_string()));
+    }
+    if let Some(chan) = out_data.iter().find(|chan| chan.len() < ness_struct.max_win_size) {
+        return Err(NessError::invalid("out_data length", chan.len(), format!(">= {}", ness_struct.max_win_size)));
+    }
     
+    //a freeze going in or out moves the speed on once per chunk
+    if ness_struct.speed.next() {
+        ness_struct.update_hops();
+    }
+
     //grab all the info from the ness_struct
-    let win_lens = &ness_struct.win_lens;
-    let out_frame_size = ness_struct.max_win_size*3;
-    let num_channels = ness_struct.num_channels;
-    let hops = &ness_struct.hops;
-    let loops = &ness_struct.loops;
-    let in_wins = &ness_struct.in_wins;
-    let filters = &ness_struct.filters;
-    let extreme = ness_struct.extreme;
     let max_win_size = ness_struct.max_win_size; //use the max_win_size by default, but be able to set it
-    let win_size_divisor = ness_struct.win_size_divisor;
-    let num_slices = ness_struct.num_slices;
-    let filter_on: usize = ness_struct.filter_on;
-    let indata = &ness_struct.in_chunk;
+    let params = ChunkParams { loops: ness_struct.loops, num_ffts: ness_struct.num_ffts, max_win_size, win_size_divisor: ness_struct.win_size_divisor };
+    //bands with their own stretch read their own input
+    let in_chunk = &ness_struct.in_chunk;
+    let band_in_chunks = &ness_struct.band_in_chunks;
+    let indata = |band: usize, chan: usize| -> &[f64] {
+        if band_in_chunks[band].is_empty() {
+            &in_chunk[chan]
+        } else {
+            &band_in_chunks[band][chan]
+        }
+    };
     let chunk_point = 0;
     
-    let mut out_temp0 = vec![0.0; out_frame_size];
-    let mut out_temp1 = vec![0.0; out_frame_size];
-    let mut out_temp2 = vec![0.0; out_frame_size];
-    let mut out_temp3 = vec![0.0; out_frame_size];
-    let mut out_temp4 = vec![0.0; out_frame_size];
-    let mut out_temp5 = vec![0.0; out_frame_size];
-    let mut out_temp6 = vec![0.0; out_frame_size];
-    let mut out_temp7 = vec![0.0; out_frame_size];
-    let mut out_temp8 = vec![0.0; out_frame_size];
-    let mut out_temp9 = vec![0.0; out_frame_size];
-    
-    let mut out_frame0 = vec![0.0; out_frame_size * num_channels];
-    let mut out_frame1 = vec![0.0; out_frame_size * num_channels];
-    let mut out_frame2 = vec![0.0; out_frame_size * num_channels];
-    let mut out_frame3 = vec![0.0; out_frame_size * num_channels];
-    let mut out_frame4 = vec![0.0; out_frame_size * num_channels];
-    let mut out_frame5 = vec![0.0; out_frame_size * num_channels];
-    let mut out_frame6 = vec![0.0; out_frame_size * num_channels];
-    let mut out_frame7 = vec![0.0; out_frame_size * num_channels];
-    let mut out_frame8 = vec![0.0; out_frame_size * num_channels];
-    let mut out_frame9 = vec![0.0; out_frame_size * num_channels];
-    
-    let mut last_frame0 = ness_struct.last_frames[0].clone();
-    let mut last_frame1 = ness_struct.last_frames[1].clone();
-    let mut last_frame2 = ness_struct.last_frames[2].clone();
-    let mut last_frame3 = ness_struct.last_frames[3].clone();
-    let mut last_frame4 = ness_struct.last_frames[4].clone();
-    let mut last_frame5 = ness_struct.last_frames[5].clone();
-    let mut last_frame6 = ness_struct.last_frames[6].clone();
-    let mut last_frame7 = ness_struct.last_frames[7].clone();
-    let mut last_frame8 = ness_struct.last_frames[8].clone();
-    let mut last_frame9 = ness_struct.last_frames[9].clone();
-
-    //println!("{}", num_slices);
-    
-    if num_slices == 1 {
-        let mut paul_win_index:usize = 5;
-        if ness_struct.paulstretch_win_size == 2 {paul_win_index = 6};
-        if ness_struct.paulstretch_win_size == 3 {paul_win_index = 7};
-
-        last_frame0 = ness_struct.last_frames[paul_win_index].clone();
-
-        for chan_num in 0..num_channels {
-            
-            let win_len = win_lens[paul_win_index];
-            
-            //out_temp will be the chunk of audio to write, then 4 "last_frames", one for each of the possible subslices
-            out_temp0 = process_sliced_chunk(
-                &indata[chan_num],
-                chunk_point, 
-                win_len,
-                0,//filter_on,  //force the filter to be off
-                &hops[paul_win_index],
-                *loops,
-                in_wins[paul_win_index].clone(),
-                //ness_wins[5].clone(),
-                filters[paul_win_index].clone(),
-                last_frame0.clone(),
-                chan_num,
-                extreme,
-                max_win_size,
-                out_frame_size,
-                win_size_divisor
-            );
-            
-            //put the last_frame data back into the last frame so it is there when we loop around to the next chunk
-            for i in 0..(win_len * 2) {
-                last_frame0[chan_num * win_len * 2 + i] =
-                out_temp0[max_win_size + i];
-            }
-            //grab the out_frame from the out_temp
-            //the out_frame is a flat array with spaces for all channels of output audio
-            //it is stored [channel0][channel1]..etc, but is flat
-            for i in 0..max_win_size {
-                out_frame0[chan_num * max_win_size + i] = out_temp0[i];
-            }
-            
-        };
-
-        ness_struct.last_frames[paul_win_index] = last_frame0.clone();
-        
-        
+    let bands = &ness_struct.bands;
+    if ness_struct.units.len() == 1 {
+        let unit = &mut ness_struct.units[0];
+        unit.process(&bands[unit.band], indata(unit.band, unit.chan), chunk_point, params)?;
     } else {
-        
-        //super ugly, but as far as I know, this is the only way to borrow from last_frame and then write back into it
-        thread::scope(|s| {
-            s.spawn(|_| {
-                for chan_num in 0..num_channels {
-                    //out_temp will be the chunk of audio to write, then 4 "last_frames", one for each of the possible subslices
-                    out_temp0 = process_sliced_chunk(
-                        &indata[chan_num],
-                        chunk_point,
-                        win_lens[0],
-                        filter_on,
-                        &hops[0],
-                        *loops,
-                        in_wins[0].clone(),
-                        filters[0].clone(),
-                        last_frame0.clone(),
-                        chan_num,
-                        extreme,
-                        max_win_size,
-                        out_frame_size,
-                        win_size_divisor
-                    );
-                    
-                    //put the last_frame data back into the last frame so it is there when we loop around to the next chunk
-                    for i in 0..(win_lens[0] * 2) {
-                        last_frame0[chan_num * win_lens[0] * 2 + i] =
-                        out_temp0[max_win_size + i];
-                    }
-                    //grab the out_frame from the out_temp
-                    //the out_frame is a flat array with spaces for all channels of output audio
-                    //it is stored [channel0][channel1]..etc, but is flat
-                    for i in 0..max_win_size {
-                        out_frame0[chan_num * max_win_size + i] = out_temp0[i];
-                    }
+        //each (slice, channel) unit is a task on the thread pool and only touches its own buffers
+        //the pool's work stealing keeps every thread busy until the last units are done
+        //the pool is started on the first chunk that needs it and kept for the rest
+        let pool = match &mut ness_struct.pool {
+            Some(pool) => pool,
+            None => {
+                let mut builder = rayon::ThreadPoolBuilder::new().thread_name(|index| format!("ness-slice-{}", index));
+                if let Some(num_threads) = ness_struct.num_threads {
+                    builder = builder.num_threads(num_threads);
                 }
-            });
-            if num_slices > 1 {
-                s.spawn(|_| {
-                    for chan_num in 0..num_channels {
-                        out_temp1 = process_sliced_chunk(
-                            &indata[chan_num],
-                            chunk_point,
-                            win_lens[1],
-                            filter_on,
-                            &hops[1],
-                            *loops,
-                            in_wins[1].clone(),
-                            filters[1].clone(),
-                            last_frame1.clone(),
-                            chan_num,
-                            extreme,
-                            max_win_size,
-                            out_frame_size,
-                            win_size_divisor
-                        );
-                        for i in 0..(win_lens[1] * 2) {
-                            last_frame1[chan_num * win_lens[1] * 2 + i] =
-                            out_temp1[max_win_size + i];
-                        }
-                        for i in 0..max_win_size {
-                            out_frame1[chan_num * max_win_size + i] = out_temp1[i];
-                        }
-                    }
-                });
-            };
-            if num_slices > 2 {
-                s.spawn(|_| {
-                    for chan_num in 0..num_channels {
-                        out_temp2 = process_sliced_chunk(
-                            &indata[chan_num],
-                            chunk_point,
-                            win_lens[2],
-                            filter_on,
-                            &hops[2],
-                            *loops,
-                            in_wins[2].clone(),
-                            filters[2].clone(),
-                            last_frame2.clone(),
-                            chan_num,
-                            extreme,
-                            max_win_size,
-                            out_frame_size,
-                            win_size_divisor
-                        );
-                        for i in 0..(win_lens[2] * 2) {
-                            last_frame2[chan_num * win_lens[2] * 2 + i] =
-                            out_temp2[max_win_size + i];
-                        }
-                        for i in 0..max_win_size {
-                            out_frame2[chan_num * max_win_size + i] = out_temp2[i];
-                        }
-                    }
-                });
-            };
-            if num_slices > 3 {
-                s.spawn(|_| {
-                    for chan_num in 0..num_channels {
-                        out_temp3 = process_sliced_chunk(
-                            &indata[chan_num],
-                            chunk_point,
-                            win_lens[3],
-                            filter_on,
-                            &hops[3],
-                            *loops,
-                            in_wins[3].clone(),
-                            filters[3].clone(),
-                            last_frame3.clone(),
-                            chan_num,
-                            extreme,
-                            max_win_size,
-                            out_frame_size,
-                            win_size_divisor
-                        );
-                        for i in 0..(win_lens[3] * 2) {
-                            last_frame3[chan_num * win_lens[3] * 2 + i] =
-                            out_temp3[max_win_size + i];
-                        }
-                        for i in 0..max_win_size {
-                            out_frame3[chan_num * max_win_size + i] = out_temp3[i];
-                        }
-                    }
-                });
-            };
-            if num_slices > 4 {
-                s.spawn(|_| {
-                    for chan_num in 0..num_channels {
-                        out_temp4 = process_sliced_chunk(
-                            &indata[chan_num],
-                            chunk_point,
-                            win_lens[4],
-                            filter_on,
-                            &hops[4],
-                            *loops,
-                            in_wins[4].clone(),
-                            filters[4].clone(),
-                            last_frame4.clone(),
-                            chan_num,
-                            extreme,
-                            max_win_size,
-                            out_frame_size,
-                            win_size_divisor
-                        );
-                        for i in 0..(win_lens[4] * 2) {
-                            last_frame4[chan_num * win_lens[4] * 2 + i] =
-                            out_temp4[max_win_size + i];
-                        }
-                        for i in 0..max_win_size {
-                            out_frame4[chan_num * max_win_size + i] = out_temp4[i];
-                        }
-                    }
-                });
-            };
-            if num_slices > 5 {
-                s.spawn(|_| {
-                    for chan_num in 0..num_channels {
-                        out_temp5 = process_sliced_chunk(
-                            &indata[chan_num],
-                            chunk_point,
-                            win_lens[5],
-                            filter_on,
-                            &hops[5],
-                            *loops,
-                            in_wins[5].clone(),
-                            filters[5].clone(),
-                            last_frame5.clone(),
-                            chan_num,
-                            extreme,
-                            max_win_size,
-                            out_frame_size,
-                            win_size_divisor
-                        );
-                        for i in 0..(win_lens[5] * 2) {
-                            last_frame5[chan_num * win_lens[5] * 2 + i] =
-                            out_temp5[max_win_size + i];
-                        }
-                        for i in 0..max_win_size {
-                            out_frame5[chan_num * max_win_size + i] = out_temp5[i];
-                        }
-                    }
-                });
-            };
-            if num_slices > 6 {
-                s.spawn(|_| {
-                    for chan_num in 0..num_channels {
-                        out_temp6 = process_sliced_chunk(
-                            &indata[chan_num],
-                            chunk_point,
-                            win_lens[6],
-                            filter_on,
-                            &hops[6],
-                            *loops,
-                            in_wins[6].clone(),
-                            filters[6].clone(),
-                            last_frame6.clone(),
-                            chan_num,
-                            extreme,
-                            max_win_size,
-                            out_frame_size,
-                            win_size_divisor
-                        );
-                        for i in 0..(win_lens[6] * 2) {
-                            last_frame6[chan_num * win_lens[6] * 2 + i] =
-                            out_temp6[max_win_size + i];
-                        }
-                        for i in 0..max_win_size {
-                            out_frame6[chan_num * max_win_size + i] = out_temp6[i];
-                        }
-                    }
-                });
-            };
-            if num_slices > 7 {
-                s.spawn(|_| {
-                    for chan_num in 0..num_channels {
-                        out_temp7 = process_sliced_chunk(
-                            &indata[chan_num],
-                            chunk_point,
-                            win_lens[7],
-                            filter_on,
-                            &hops[7],
-                            *loops,
-                            in_wins[7].clone(),
-                            filters[7].clone(),
-                            last_frame7.clone(),
-                            chan_num,
-                            extreme,
-                            max_win_size,
-                            out_frame_size,
-                            win_size_divisor
-                        );
-                        for i in 0..(win_lens[7] * 2) {
-                            last_frame7[chan_num * win_lens[7] * 2 + i] =
-                            out_temp7[max_win_size + i];
-                        }
-                        for i in 0..max_win_size {
-                            out_frame7[chan_num * max_win_size + i] = out_temp7[i];
-                        }
-                    }
-                });
-            };
-            if num_slices > 8 {
-                s.spawn(|_| {
-                    for chan_num in 0..num_channels {
-                        out_temp8 = process_sliced_chunk(
-                            &indata[chan_num],
-                            chunk_point,
-                            win_lens[8],
-                            filter_on,
-                            &hops[8],
-                            *loops,
-                            in_wins[8].clone(),
-                            filters[8].clone(),
-                            last_frame8.clone(),
-                            chan_num,
-                            extreme,
-                            max_win_size,
-                            out_frame_size,
-                            win_size_divisor
-                        );
-                        for i in 0..(win_lens[8] * 2) {
-                            last_frame8[chan_num * win_lens[8] * 2 + i] =
-                            out_temp8[max_win_size + i];
-                        }
-                        for i in 0..max_win_size {
-                            out_frame8[chan_num * max_win_size + i] = out_temp8[i];
-                        }
-                    }
-                });
-            };
-            if num_slices > 9 {
-                s.spawn(|_| {
-                    for chan_num in 0..num_channels {
-                        out_temp9 = process_sliced_chunk(
-                            &indata[chan_num],
-                            chunk_point,
-                            win_lens[9],
-                            filter_on,
-                            &hops[9],
-                            *loops,
-                            in_wins[9].clone(),
-                            filters[9].clone(),
-                            last_frame9.clone(),
-                            chan_num,
-                            extreme,
-                            max_win_size,
-                            out_frame_size,
-                            win_size_divisor
-                        );
-                        for i in 0..(win_lens[9] * 2) {
-                            last_frame9[chan_num * win_lens[9] * 2 + i] =
-                            out_temp9[max_win_size + i];
-                        }
-                        for i in 0..max_win_size {
-                            out_frame9[chan_num * max_win_size + i] = out_temp9[i];
-                        }
-                    }
-                });
-            };
-        })
-        .unwrap();
-
-        //copy the last_frame data back into the ness_struct
-        ness_struct.last_frames[0] = last_frame0.clone();
-        ness_struct.last_frames[1] = last_frame1.clone();
-        ness_struct.last_frames[2] = last_frame2.clone();
-        ness_struct.last_frames[3] = last_frame3.clone();
-        ness_struct.last_frames[4] = last_frame4.clone();
-        ness_struct.last_frames[5] = last_frame5.clone();
-        ness_struct.last_frames[6] = last_frame6.clone();
-        ness_struct.last_frames[7] = last_frame7.clone();
-        ness_struct.last_frames[8] = last_frame8.clone();
-        ness_struct.last_frames[9] = last_frame9.clone();
+                ness_struct.pool.insert(builder.build()?)
+            }
+        };
+        let units = &mut ness_struct.units;
+        pool.install(|| {
+            units.par_iter_mut().with_max_len(1).try_for_each(|unit| {
+                unit.process(&bands[unit.band], indata(unit.band, unit.chan), chunk_point, params)
+            })
+        })?;
     }
-
     
-    
-    let mut out_data: Vec<Vec<f64>> = vec![vec![0.0; max_win_size]; num_channels];
-    
-    //out_frameN has the M channels of audio spread out accross a single array
-    //with max_win_size frames per channel
-    for chan_num in 0..num_channels {
-        let read_point = chan_num * max_win_size;
-        for i in 0..max_win_size {
-            out_data[chan_num][i] = out_frame0[read_point + i]
-            + out_frame1[read_point + i]
-            + out_frame2[read_point + i]
-            + out_frame3[read_point + i]
-            + out_frame4[read_point + i]
-            + out_frame5[read_point + i]
-            + out_frame6[read_point + i]
-            + out_frame7[read_point + i]
-            + out_frame8[read_point + i]
-            + out_frame9[read_point + i];
+    //every unit has max_win_size frames of one channel
+    for out_chan in out_data.iter_mut() {
+        out_chan[..max_win_size].fill(0.0);
+    }
+    //a gain, mute or solo change ramps in across the chunk
+    for unit in ness_struct.units.iter() {
+        let (from, to) = ness_struct.mix.gain_ramp(unit.band);
+        let step = (to - from) / max_win_size as f64;
+        for (iter, (out, sample)) in out_data[unit.chan][..max_win_size].iter_mut().zip(unit.out.iter()).enumerate() {
+            *out += sample * (from + step * iter as f64);
         }
     }
+    ness_struct.mix.settle();
     //out_data is a multidimensional array with max_win_size frames per channel
-    return out_data;
+    Ok(())
 }
 
 //this is the code that does the actual randomizing of phases
+//writes the half win_len frame multiplied by the ness_window followed by the flipped frame into bufs.frame
+#[allow(clippy::too_many_arguments)]
 fn process_microframe(
-    spectrum: Vec<Complex<f64>>,
+    spectrum: &[Complex<f64>],
     last_frame: &[f64],
-    filt_win: Vec<f64>,
-    filter_on: usize,
-    extreme: usize,
-) -> Vec<f64> {
+    filt_win: &[f64],
+    filter_on: bool,
+    num_ffts: usize,
+    rng: &mut NessRng,
+    ifft: &dyn ComplexToReal<f64>,
+    bufs: &mut FftScratch,
+) -> Result<(), NessError> {
     let half_win_len = spectrum.len() - 1;
     let win_len = half_win_len * 2;
-    
-    //sets up the ifft planner
-    let mut real_planner = RealFftPlanner::<f64>::new();
-    let ifft = real_planner.plan_fft_inverse(win_len);
-    let mut out_frame = ifft.make_output_vec();
-    let mut fin_out_frame = ifft.make_output_vec();
-    let mut flipped_frame = vec![0.0; win_len];
-    let mut spectrum_out = real_planner.plan_fft_forward(win_len).make_output_vec();
-    
-    //the correlation values used
-    let mut correlation = 0.0;
-    let mut corr_temp = 0.0;
-    let mut corr_abs;
-    let mut c_a_temp = 0.0;
-    let mut num_ffts = 1;
-    
-    //sets up the ffts based on the extreme setting
-    if extreme == 1 {
-        num_ffts = 10
-    }
-    if extreme == 3 {
-        num_ffts = 3
-    }
-    if extreme > 3 {
-        num_ffts = extreme
-    }
-    
+    let FftScratch { spectrum_out, out_frame, scratch, best_frame, ness_window, frame, .. } = bufs;
+    assert_eq!(spectrum_out.len(), spectrum.len());
+
     //goes through and makes all the ffts to compare correlation on
+    let mut search = CorrelationSearch::new();
     for _count in 0..num_ffts {
         //0s the bins and randomizes the phases
-        for iter in 1..spectrum.len()-1 {
-            
-            let mut temp = spectrum[iter].to_polar();
-            if filter_on == 1 {temp.0 = temp.0 * filt_win[iter]}; //multiply by the filter if filter is on
-            temp.1 = rand::thread_rng().gen_range(-PI/2.0..PI/2.0);
-            spectrum_out[iter] = Complex::from_polar(temp.0, temp.1);
-        }
-        
-        assert_eq!(spectrum_out.len(), spectrum.len());
-        //performs the ifft
-        ifft.process(&mut spectrum_out, &mut out_frame).unwrap();
-        
-        //gets half the frame and checks correlation with the previous frame
-        //let half_vec0 = &last_frame[win_len/2..];
-        let half_current_frame = &out_frame[..half_win_len];
-        
-        let temp_sum: f64 = last_frame.iter().sum();
-        if temp_sum != 0.0 {
-            let r: f64 = last_frame
-            .iter()
-            .zip(half_current_frame.iter())
-            .map(|(x, y)| x * y)
-            .sum();
-            let s: f64 = last_frame
-            .iter()
-            .zip(last_frame.iter())
-            .map(|(x, y)| x * y)
-            .sum();
-            corr_temp = r / s;
-        }
-        corr_abs = corr_temp.abs();
-        
-        //if the correlation is better use this one
-        if corr_abs > c_a_temp {
-            correlation = corr_temp;
-            c_a_temp = correlation.abs();
-            fin_out_frame = out_frame.clone();
-        }
+        randomize_bins(spectrum, spectrum_out, filt_win, filter_on, rng, 1..half_win_len);
+        inverse_fft(ifft, spectrum_out, out_frame, scratch)?;
+        //checks half the frame for correlation with the previous frame - if the correlation is better use this one
+        search.offer(last_frame, out_frame, best_frame);
     }
-    corr_abs = correlation.abs();
-    if correlation == 0.0 {
-        fin_out_frame = out_frame.clone()
-    }
-    //inverts the randomized signal if the correlation is negative
-    for i in 0..win_len {
-        if correlation < 0.0 {
-            flipped_frame[i] = -1.0 * fin_out_frame[i];
-        } else {
-            flipped_frame[i] = fin_out_frame[i];
-        }
-    }
-
-    //gets the ness_window
-    if corr_abs>1.0 {corr_abs=1.0};
+    search.settle(out_frame, best_frame);
+    search.orient(best_frame);
 
-    let ness_window = make_ness_window(win_len, corr_abs);
-    
-    let mut out_frame2 = vec![0.0; win_len];
-    
-    //multiples the start of the ness_window by the start of the frame
-    //and the end of the ness_window by the end of the frame
-    for i in 0..half_win_len {
-        out_frame2[i] =
-        flipped_frame[i] * ness_window[i] + (last_frame[i] * ness_window[half_win_len-1-i]);
-    }
+    //gets the ness_window and crossfades the start of the frame with the end of the last one
+    fill_ness_window(ness_window, win_len, search.corr_abs(), 0..half_win_len);
+    crossfade(frame, best_frame, last_frame, ness_window, 0..half_win_len);
     //add the second half of the flipped frame (no ness_window) to check for correlation on the next loop
-    for i in 0..half_win_len {
-        out_frame2[i + half_win_len] = flipped_frame[i + half_win_len];
-    }
-    
-    //returns a frame that contains the half win_len frame multiplied by the ness_window followed by the flipped frame (necessary for checking the next correlation)
-    
-    return out_frame2;
+    frame[half_win_len..].copy_from_slice(&best_frame[half_win_len..]);
+
+    Ok(())
 }
 
 
 
-//creates a chunk of audio that is the size of the max_win_size
-fn process_sliced_chunk(
-    indata: &Vec<f64>,
+//creates a chunk of audio that is the size of the max_win_size for one channel of one slice
+//the audio goes into out_chunk and the new last frames for each subslice go into last_frame
+#[allow(clippy::too_many_arguments, clippy::needless_return)]
+pub(crate) fn process_sliced_chunk(
+    indata: &[f64],
     chunk_point: usize,
     win_len: usize,
-    filter_on: usize,
+    filter_on: bool,
     hop: &f64,
     loops: usize,
-    in_win: Vec<f64>,
+    in_win: &[f64],
     //ness_wins: Vec<Vec<f64>>,
-    filters: Vec<Vec<f64>>,
-    mut last_frame: Vec<f64>,
-    chan_num: usize,
+    filters: &[Vec<f64>],
+    last_frame: &mut [f64],
+    out_chunk: &mut [f64],
+    carry: &mut [f64],
+    first_out: &mut usize,
+    rng: &mut NessRng,
+    fft: &dyn RealToComplex<f64>,
+    ifft: &dyn ComplexToReal<f64>,
+    bufs: &mut FftScratch,
     //num_slices: usize,
-    extreme: usize,
+    num_ffts: usize,
     max_win_size: usize,
-    out_frame_size: usize,
     win_size_divisor: usize,
-) -> Vec<f64> {
+) -> Result<(), NessError> {
 
     let half_win_len = win_len / 2;
     
+    //there is a stretch point every hop - the points where we will be reading from the indata
+    //and an out point every half_win_len - the points where we will be writing into the out_chunk buffer
+    //when half_win_len doesn't divide max_win_size the out points run on from the last chunk, starting at first_out,
+    //and the end of the last frame spills into carry, which starts the next chunk
+    let num_points = (max_win_size - *first_out).div_ceil(half_win_len);
+    let first_point = *first_out as f64 / half_win_len as f64;
     
-    //the vector of stretch points contains the points where we will be reading from the indata
-    let mut stretch_points = vec![0; (max_win_size / half_win_len) as usize];
-    for iter in 0..stretch_points.len() {
-        stretch_points[iter] =
-        chunk_point + (hop * iter as f64) as usize + (max_win_size / 2 - half_win_len) as usize;
-    }
-    
-    //the vector of out_points contains the points where we will be writing into the out_chunk buffer
-    let mut out_points = vec![0; stretch_points.len()];
-    for iter in 0..out_points.len() {
-        out_points[iter] = iter * half_win_len;
-    }
-    
-    //this is the audio we will be writing to disk plus the 4 extreme 2 last_frame vectors
-    //it will store the full audio chunk
-    let mut out_chunk = vec![0.0; out_frame_size];
-    
-    //this is the lookup location into the last_frame - since the last_frame contains "num_channels" locations with 4 half_win sized frames at each location
-    let chan_point = chan_num * win_len * 2;
+    out_chunk.fill(0.0);
+    out_chunk[..half_win_len].copy_from_slice(carry);
+    carry.fill(0.0);
     
     //big loop over the stretch points
-    for big_iter in 0..(stretch_points.len()/win_size_divisor) {
+    for big_iter in 0..(num_points/win_size_divisor) {
+        let stretch_point = chunk_point + (hop * (big_iter as f64 + first_point)) as usize + (max_win_size / 2 - half_win_len);
+        let out_spot = *first_out + big_iter * half_win_len;
+        
         //for efficiency, does the fft once for the frame
-        let mut real_planner = RealFftPlanner::<f64>::new();
-        let fft = real_planner.plan_fft_forward(win_len);
-        let mut spectrum = fft.make_output_vec();
-        let mut part = vec![0.0; win_len];
         for i in 0..win_len {
-            part[i] = indata[stretch_points[big_iter] + i] * in_win[i];
+            bufs.part[i] = indata[stretch_point + i] * in_win[i];
         }
-        fft.process(&mut part, &mut spectrum).unwrap();
+        fft.process_with_scratch(&mut bufs.part, &mut bufs.spectrum, &mut bufs.scratch)?;
+        //the spectrum is moved out while process_microframe uses the rest of the buffers
+        let spectrum = std::mem::take(&mut bufs.spectrum);
         
         //will loop once, twice, or 4 times depending on algorithm
         for i in 0..loops {
-            //makes the linquitz-riley window at the cuttoff points
-            let filt_win = filters[i].clone();
+            //the linquitz-riley window at the cuttoff points
+            let filt_win = &filters[i];
             let last_frame_slice =
-            &last_frame[chan_point + i * half_win_len..chan_point + (i + 1) * half_win_len];
+            &mut last_frame[i * half_win_len..(i + 1) * half_win_len];
             
-            //process_microframe does the actual processing of the phase and returns the phase randomized frame
-            let out_frame =
-            process_microframe(spectrum.clone(), last_frame_slice, filt_win, filter_on, extreme);//&ness_wins,
+            //process_microframe does the actual processing of the phase and leaves the phase randomized frame in bufs.frame
+            process_microframe(&spectrum, last_frame_slice, filt_win, filter_on, num_ffts, rng, ifft, bufs)?;//&ness_wins,
             
             //get the current frame to return as the last
-            for i2 in 0..half_win_len {
-                last_frame[chan_point + i * half_win_len + i2] = out_frame[half_win_len + i2];
-            }
+            last_frame_slice.copy_from_slice(&bufs.frame[half_win_len..]);
             //put the half frame sound output into the out_data starting at the outpoints
-            let out_spot = out_points[big_iter] as usize;
             for i2 in 0..half_win_len {
-                out_chunk[out_spot + i2] += out_frame[i2] / win_len as f64;
+                let sample = bufs.frame[i2] / win_len as f64;
+                if out_spot + i2 < max_win_size {
+                    out_chunk[out_spot + i2] += sample;
+                } else {
+                    carry[out_spot + i2 - max_win_size] += sample;
+                }
             }
         }
+        bufs.spectrum = spectrum;
     }
+    *first_out = *first_out + num_points * half_win_len - max_win_size;
     
-    //put the last frame output into the out_chunk at a point based on the channel being processed
-    for i in 0..win_len * 2 {
-        out_chunk[max_win_size + i] = last_frame[chan_point + i];
-    }
-    return out_chunk;
-}
-
-//makes the the first half of the ness window in accordance with the correlation number provided
-fn make_ness_window(len: usize, correlation: f64) -> Vec<f64> { 
-    let lendiv2 = len/2;
-    let mut floats: Vec<f64> = vec![0.0; lendiv2];
-    let mut vals: Vec<f64> = vec![0.0; lendiv2];
-    //lendiv2 = lendiv2 ;
-    for iter in 0..(lendiv2) {
-        floats[iter] = iter as f64 / ((len-1) as f64 / 2.0);
-    }
-    //floats.push(0.0);
-    for iter in 0..lendiv2 {
-        let fs = f64::powf((floats[iter] * PI / 2.0).tan(), 2.0);
-        vals[iter] = fs * (1.0 / (1.0 + (2.0 * fs * (correlation)) + f64::powf(fs, 2.0))).sqrt();
-    }
-    return vals;
+    return Ok(());
 }
 
 //makes the linkwitz-riley fft crossfade window, which effectively 0s out the bins wanted in the spectral slice
-//high pass, low pass, and bandbass versions
-fn make_lr_lp_window(len: usize, hi_bin: f64, order: f64) -> Vec<f64> {
+//high pass, low pass, and bandbass versions - shape swaps the linkwitz-riley curve for one of the other crossover fades
+#[allow(clippy::needless_range_loop, clippy::needless_return)]
+fn make_lr_lp_window(len: usize, hi_bin: f64, order: f64, shape: CrossoverShape) -> Vec<f64> {
     let mut filter = vec![1.0; len];
     if hi_bin != 0.0 {
         for i in 0..len {
-            filter[i] = 1.0 / (1.0 + (f64::powf(i as f64 / hi_bin, order)));
+            filter[i] = shape.low_pass(i as f64 / hi_bin, order);
         }
     }
     return filter;
 }
 
-fn make_lr_hp_window(len: usize, low_bin: f64, order: f64) -> Vec<f64> {
+#[allow(clippy::needless_range_loop, clippy::needless_return)]
+fn make_lr_hp_window(len: usize, low_bin: f64, order: f64, shape: CrossoverShape) -> Vec<f64> {
     let mut filter = vec![1.0; len];
     if low_bin != 0.0 {
         for i in 0..len {
-            filter[i] = 1.0 - (1.0 / (1.0 + (f64::powf(i as f64 / low_bin, order))));
+            filter[i] = shape.high_pass(i as f64 / low_bin, order);
         }
     }
     return filter;
 }
 
-fn make_lr_bp_window(len: usize, low_bin: f64, hi_bin: f64, order: f64) -> Vec<f64> {
+#[allow(clippy::needless_return)]
+fn make_lr_bp_window(len: usize, low_bin: f64, hi_bin: f64, order: f64, shape: CrossoverShape) -> Vec<f64> {
     let filter: Vec<f64>;
     if low_bin <= 0.0 {
-        filter = make_lr_lp_window(len, hi_bin, order);
+        filter = make_lr_lp_window(len, hi_bin, order, shape);
     } else {
         if hi_bin >= (len - 2) as f64 {
-            filter = make_lr_hp_window(len, low_bin, order);
+            filter = make_lr_hp_window(len, low_bin, order, shape);
         } else {
-            let lp = make_lr_lp_window(len, hi_bin, order);
-            let hp = make_lr_hp_window(len, low_bin, order);
+            let lp = make_lr_lp_window(len, hi_bin, order, shape);
+            let hp = make_lr_hp_window(len, low_bin, order, shape);
             filter = lp.iter().zip(hp.iter()).map(|(x, y)| x * y).collect();
         }
     }
     return filter;
 }
-
-//the paul stretch window is used on input - might a well be a sine or hann window
-fn make_paul_window(len: usize) -> Vec<f64> {
-    let mut part = vec![0.0; len];
-    for i in 0..len {
-        let value = i as f64 / (len as f64 - 1.0) * 2.0 - 1.0;
-        let value = f64::powf(1.0 - (f64::powf(value, 2.0)), 1.25);
-        part[i] = value;
-    }
-    return part;
-}
-
-//flops a channel array from interleaved clusters to separate files
-fn transpose<T>(v: Vec<Vec<T>>) -> Vec<Vec<T>>
-where
-T: Clone,
-{
-    assert!(!v.is_empty());
-    (0..v[0].len())
-    .map(|i| v.iter().map(|inner| inner[i].clone()).collect::<Vec<T>>())
-    .collect()
-}
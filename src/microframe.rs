@@ -0,0 +1,112 @@
+use rand::Rng;
+use realfft::ComplexToReal;
+use rustfft::num_complex::Complex;
+use std::f64::consts::PI;
+use std::ops::Range;
+
+use crate::{NessError, NessRng};
+
+//the pieces of a microframe - randomizing the phases, picking the best correlating frame and crossfading it with the last one
+//process_microframe runs them over the whole frame at once, the realtime bands run them a range at a time across callbacks,
+//so both make the same frames from the same random numbers
+
+//randomizes the phases of the bins in range, filtering their magnitudes first if the slice is filtered
+pub(crate) fn randomize_bins(
+    spectrum: &[Complex<f64>],
+    spectrum_out: &mut [Complex<f64>],
+    filt_win: &[f64],
+    filter_on: bool,
+    rng: &mut NessRng,
+    bins: Range<usize>,
+) {
+    for ((bin, out), filt) in spectrum[bins.clone()].iter().zip(spectrum_out[bins.clone()].iter_mut()).zip(filt_win[bins].iter()) {
+        let mut temp = bin.to_polar();
+        if filter_on {
+            temp.0 *= filt
+        };
+        temp.1 = rng.gen_range(-PI / 2.0..PI / 2.0);
+        *out = Complex::from_polar(temp.0, temp.1);
+    }
+}
+
+//the ifft of the randomized spectrum
+//the ifft uses spectrum_out as scratch space, so the dc and nyquist bins have to be zeroed again before every pass
+pub(crate) fn inverse_fft(ifft: &dyn ComplexToReal<f64>, spectrum_out: &mut [Complex<f64>], out_frame: &mut [f64], scratch: &mut [Complex<f64>]) -> Result<(), NessError> {
+    let last_bin = spectrum_out.len() - 1;
+    spectrum_out[0] = Complex::new(0.0, 0.0);
+    spectrum_out[last_bin] = Complex::new(0.0, 0.0);
+    ifft.process_with_scratch(spectrum_out, out_frame, scratch)?;
+    Ok(())
+}
+
+//keeps whichever of a microframe's randomized frames correlates best with the end of the last frame
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct CorrelationSearch {
+    correlation: f64,
+    corr_temp: f64,
+    c_a_temp: f64,
+}
+
+impl CorrelationSearch {
+    pub(crate) fn new() -> CorrelationSearch {
+        CorrelationSearch { correlation: 0.0, corr_temp: 0.0, c_a_temp: 0.0 }
+    }
+
+    //checks a new frame against the last one and copies it to best_frame if it correlates better than the ones before it
+    pub(crate) fn offer(&mut self, last_frame: &[f64], out_frame: &[f64], best_frame: &mut [f64]) {
+        let temp_sum: f64 = last_frame.iter().sum();
+        if temp_sum != 0.0 {
+            let r: f64 = last_frame.iter().zip(out_frame.iter()).map(|(x, y)| x * y).sum();
+            let s: f64 = last_frame.iter().map(|x| x * x).sum();
+            self.corr_temp = r / s;
+        }
+        if self.corr_temp.abs() > self.c_a_temp {
+            self.correlation = self.corr_temp;
+            self.c_a_temp = self.correlation.abs();
+            best_frame.copy_from_slice(out_frame);
+        }
+    }
+
+    //once every frame has been offered - with no correlation at all the last frame made is the one used
+    pub(crate) fn settle(&self, out_frame: &[f64], best_frame: &mut [f64]) {
+        if self.correlation == 0.0 {
+            best_frame.copy_from_slice(out_frame);
+        }
+    }
+
+    //the correlation the ness window is made for
+    pub(crate) fn corr_abs(&self) -> f64 {
+        self.correlation.abs().min(1.0)
+    }
+
+    //inverts the chosen frame, or part of it, if the correlation is negative
+    pub(crate) fn orient(&self, best_frame: &mut [f64]) {
+        if self.correlation < 0.0 {
+            for sample in best_frame.iter_mut() {
+                *sample = -*sample;
+            }
+        }
+    }
+}
+
+//fills the part of the first half of the ness window in range, in accordance with the correlation
+pub(crate) fn fill_ness_window(ness_window: &mut [f64], win_len: usize, correlation: f64, range: Range<usize>) {
+    for (iter, val) in ness_window[range.clone()].iter_mut().enumerate() {
+        *val = ness_window_value(win_len, range.start + iter, correlation);
+    }
+}
+
+fn ness_window_value(len: usize, index: usize, correlation: f64) -> f64 {
+    let float = index as f64 / ((len - 1) as f64 / 2.0);
+    let fs = f64::powf((float * PI / 2.0).tan(), 2.0);
+    fs * (1.0 / (1.0 + (2.0 * fs * (correlation)) + f64::powf(fs, 2.0))).sqrt()
+}
+
+//multiplies the start of the chosen frame by the ness window and the end of the last frame by the reversed window
+//for the samples in range - ness_window is the first half of the window, as long as last_frame
+pub(crate) fn crossfade(frame: &mut [f64], best_frame: &[f64], last_frame: &[f64], ness_window: &[f64], range: Range<usize>) {
+    let half_win_len = ness_window.len();
+    for i in range {
+        frame[i] = best_frame[i] * ness_window[i] + (last_frame[i] * ness_window[half_win_len - 1 - i]);
+    }
+}
@@ -0,0 +1,91 @@
+use crate::crossover::db_to_gain;
+use crate::{NessConfig, NessError};
+
+//the gain, mute and solo of every band, applied where the bands are added together
+//bands are numbered from the highest to the lowest, like win_lens
+//muted bands keep processing, so they come back without a gap when they are unmuted
+pub(crate) struct BandMix {
+    gains_db: Vec<f64>,
+    muted: Vec<bool>,
+    soloed: Vec<bool>,
+    //the linear gain of each band, worked out whenever one of the settings changes
+    gains: Vec<f64>,
+    //the gain each band ended the last chunk on - a chunk ramps from it to the new gain so a change doesn't click
+    previous: Vec<f64>,
+}
+
+impl BandMix {
+    pub(crate) fn new(config: &NessConfig, num_bands: usize) -> BandMix {
+        let mut mix = BandMix {
+            gains_db: config.band_gains.clone().unwrap_or_else(|| vec![0.0; num_bands]),
+            muted: vec![false; num_bands],
+            soloed: vec![false; num_bands],
+            gains: vec![1.0; num_bands],
+            previous: vec![1.0; num_bands],
+        };
+        for band in config.muted_bands.iter() {
+            mix.muted[*band] = true;
+        }
+        for band in config.soloed_bands.iter() {
+            mix.soloed[*band] = true;
+        }
+        mix.update();
+        mix.settle();
+        mix
+    }
+
+    //the linear gain a band is mixed at
+    pub(crate) fn gain(&self, band: usize) -> f64 {
+        self.gains[band]
+    }
+
+    //the gains a band ramps between across a chunk, from where the last chunk ended to the current setting
+    pub(crate) fn gain_ramp(&self, band: usize) -> (f64, f64) {
+        (self.previous[band], self.gains[band])
+    }
+
+    //a chunk has been mixed, so the next one starts from the current gains
+    pub(crate) fn settle(&mut self) {
+        self.previous.copy_from_slice(&self.gains);
+    }
+
+    pub(crate) fn set_gain(&mut self, band: usize, gain_db: f64) -> Result<(), NessError> {
+        self.check_band(band)?;
+        if !gain_db.is_finite() {
+            return Err(NessError::invalid("gain_db", gain_db, "a finite gain"));
+        }
+        self.gains_db[band] = gain_db;
+        self.update();
+        Ok(())
+    }
+
+    pub(crate) fn set_mute(&mut self, band: usize, mute: bool) -> Result<(), NessError> {
+        self.check_band(band)?;
+        self.muted[band] = mute;
+        self.update();
+        Ok(())
+    }
+
+    pub(crate) fn set_solo(&mut self, band: usize, solo: bool) -> Result<(), NessError> {
+        self.check_band(band)?;
+        self.soloed[band] = solo;
+        self.update();
+        Ok(())
+    }
+
+    fn check_band(&self, band: usize) -> Result<(), NessError> {
+        if band >= self.gains.len() {
+            return Err(NessError::invalid("band", band, format!("0..{}", self.gains.len())));
+        }
+        Ok(())
+    }
+
+    //when any band is soloed only the soloed bands are heard, and a muted band is never heard
+    fn update(&mut self) {
+        let any_solo = self.soloed.contains(&true);
+        for band in 0..self.gains.len() {
+            let heard = !self.muted[band] && (!any_solo || self.soloed[band]);
+            self.gains[band] = if heard { db_to_gain(self.gains_db[band]) } else { 0.0 };
+        }
+    }
+}
@@ -0,0 +1,481 @@
+use realfft::{ComplexToReal, RealToComplex};
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::band::FftScratch;
+use crate::microframe::{crossfade, fill_ness_window, inverse_fft, randomize_bins, CorrelationSearch};
+use crate::mix::BandMix;
+use crate::{config_for_sample_rate, make_rng, NessConfig, NessError, NessRng, NessStruct};
+
+//the input ring buffer holds this many max_win_size windows of live input
+const INPUT_BUFFER_WINDOWS: usize = 8;
+//the bin loops are split into steps of this many bins so the big windows don't land in a single callback
+const STEP_SIZE: usize = 4096;
+//a callback may do this many times the average work, so a band that falls behind catches up within its frame
+const WORK_HEADROOM: f64 = 2.0;
+
+//real-time version of the stretch, for use inside an audio callback
+//process takes and returns host sized blocks and never allocates, locks or spawns threads
+//
+//instead of making a whole max_win_size chunk at once, every slice makes one frame at a time,
+//and the ffts for a frame are spread over the callbacks that play the slice's previous frame
+//so a 65536 sample window costs roughly one fft per callback instead of all of them at once
+//
+//the steps of all the bands share a budget per callback, and go to whichever band's frame is heard soonest
+//the bands' frames are staggered so their ffts don't all fall due in the same callback
+//
+//the stretch reads the live input more slowly than it arrives, so when the read position falls
+//too far behind (more than the input buffer can hold) it jumps forward to recent input
+pub struct RealtimeStretcher {
+    num_channels: usize,
+    max_win_size: usize,
+    max_block_size: usize,
+    dur_mult: f64,
+    latency: usize,
+    //samples processed since the stretcher started
+    time: usize,
+    //moves the read position forward whenever the stretch has to catch up with the live input
+    read_offset: f64,
+    //ring buffers indexed by time, one per channel
+    input: Vec<Vec<f64>>,
+    output: Vec<Vec<f64>>,
+    bands: Vec<RealtimeBand>,
+    mix: BandMix,
+    //the average work of the bands per sample, in samples and bins touched
+    work_rate: f64,
+    //work a callback did past its budget, which comes out of the next ones
+    overrun: usize,
+}
+
+//what every band reads while it catches up with one block
+struct BlockContext<'a> {
+    latency: usize,
+    read_offset: f64,
+    max_win_size: usize,
+    input: &'a [Vec<f64>],
+}
+
+//one spectral slice, processed frame by frame
+struct RealtimeBand {
+    win_len: usize,
+    half_win_len: usize,
+    hop: f64,
+    loops: usize,
+    num_ffts: usize,
+    //how much earlier than the other bands this band works on its frames
+    phase: usize,
+    filter_on: bool,
+    gain: f64,
+    in_win: Vec<f64>,
+    filters: Vec<Vec<f64>>,
+    fft: Arc<dyn RealToComplex<f64>>,
+    ifft: Arc<dyn ComplexToReal<f64>>,
+    //the frame being worked on and how many of its steps are done
+    frame: usize,
+    step: usize,
+    channels: Vec<RealtimeBandChannel>,
+}
+
+//the per channel state and scratch space of a slice, all allocated up front
+struct RealtimeBandChannel {
+    bufs: FftScratch,
+    //the second half of the last frame of each subslice
+    last_frame: Vec<f64>,
+    search: CorrelationSearch,
+    rng: NessRng,
+}
+
+//one step of a frame - the loops over bins and samples are cut into ranges of STEP_SIZE
+enum Step {
+    //reads the input and ffts it
+    Forward,
+    //randomizes some of the bins of one of a subslice's randomized frames
+    Randomize { sub: usize, count: usize, bins: Range<usize> },
+    //iffts a randomized frame and checks its correlation
+    Inverse { sub: usize },
+    //flips some of the chosen frame if it correlates negatively and fills in that much of the ness window
+    Window { range: Range<usize> },
+    //crossfades some of the chosen frame with the last one and adds it to the output
+    Crossfade { sub: usize, range: Range<usize> },
+}
+
+impl RealtimeStretcher {
+    //max_block_size is the largest block the host will pass to process
+    pub fn new(config: &NessConfig, sample_rate: u32, num_channels: usize, max_block_size: usize) -> Result<RealtimeStretcher, NessError> {
+        let config = config_for_sample_rate(config, sample_rate)?;
+        //builds the windows and filters exactly as the offline stretch does
+        let ness_struct = NessStruct::new(&config, sample_rate, num_channels)?;
+        let max_win_size = ness_struct.max_win_size;
+        if config.stretch_curve.is_some() {
+            return Err(NessError::invalid("stretch_curve", &config.stretch_curve, "a fixed dur_mult in realtime"));
+        }
+        //a band that stretches less than dur_mult would have to read input that hasn't arrived yet
+        if ness_struct.band_in_chunks.iter().any(|band_in_chunk| !band_in_chunk.is_empty()) {
+            return Err(NessError::invalid("band_dur_mults", &ness_struct.band_dur_mults, format!("every band at dur_mult ({}) in realtime", config.dur_mult)));
+        }
+        if max_block_size == 0 || max_block_size > max_win_size / 2 {
+            return Err(NessError::invalid("max_block_size", max_block_size, format!("1..={}", max_win_size / 2)));
+        }
+
+        //every band of the NessStruct gets a realtime twin that shares its windows, filters and fft plans
+        let num_bands = ness_struct.bands.len();
+        let mut bands = Vec::with_capacity(num_bands);
+        for (iter, band) in ness_struct.bands.iter().enumerate() {
+            let win_len = band.win_len;
+            let half_win_len = win_len / 2;
+            let fft = band.fft.clone();
+            let ifft = band.ifft.clone();
+            let channels = (0..num_channels)
+                .map(|chan| RealtimeBandChannel {
+                    bufs: FftScratch::new(&*fft, &*ifft),
+                    last_frame: vec![0.0; half_win_len * ness_struct.loops],
+                    search: CorrelationSearch::new(),
+                    rng: make_rng(config.seed, band.slice, chan),
+                })
+                .collect();
+            //the bands' windows are powers of two, so their frames would otherwise all start together
+            //each band is moved a different part of its own frame earlier - a frame can only start early by as much as its
+            //window is smaller than max_win_size, or its input won't have arrived yet
+            let phase = (half_win_len * (num_bands - 1 - iter) / num_bands).min(max_win_size - win_len);
+            bands.push(RealtimeBand {
+                win_len,
+                half_win_len,
+                hop: band.hop,
+                loops: ness_struct.loops,
+                num_ffts: ness_struct.num_ffts,
+                phase,
+                filter_on: band.filter_on,
+                gain: band.gain,
+                in_win: band.in_win.clone(),
+                filters: band.filters.clone(),
+                fft,
+                ifft,
+                frame: 0,
+                step: 0,
+                channels,
+            });
+        }
+        let work_rate = bands.iter().map(|band| band.frame_work() as f64 / band.half_win_len as f64).sum();
+
+        Ok(RealtimeStretcher {
+            num_channels,
+            max_win_size,
+            max_block_size,
+            dur_mult: config.dur_mult,
+            latency: Self::latency_for(max_win_size),
+            time: 0,
+            read_offset: 0.0,
+            input: vec![vec![0.0; max_win_size * INPUT_BUFFER_WINDOWS]; num_channels],
+            output: vec![vec![0.0; max_win_size * 2]; num_channels],
+            mix: BandMix::new(&config, bands.len()),
+            bands,
+            work_rate,
+            overrun: 0,
+        })
+    }
+
+    //a slice's frame can only be made once all of its input has arrived, and its ffts are spread
+    //over the half window before it is heard, so output runs 1.5 max windows behind the input
+    fn latency_for(max_win_size: usize) -> usize {
+        max_win_size + max_win_size / 2
+    }
+
+    //how many samples the output lags the input - the first stretched sound appears after this many samples
+    pub fn latency(&self) -> usize {
+        self.latency
+    }
+
+    pub fn num_channels(&self) -> usize {
+        self.num_channels
+    }
+
+    pub fn max_block_size(&self) -> usize {
+        self.max_block_size
+    }
+
+    //the mix of the bands, numbered from 0 for the highest
+    //frames are mixed as they are made, so a change is heard within half a window
+    pub fn set_band_gain(&mut self, band: usize, gain_db: f64) -> Result<(), NessError> {
+        self.mix.set_gain(band, gain_db)
+    }
+
+    pub fn set_band_mute(&mut self, band: usize, mute: bool) -> Result<(), NessError> {
+        self.mix.set_mute(band, mute)
+    }
+
+    pub fn set_band_solo(&mut self, band: usize, solo: bool) -> Result<(), NessError> {
+        self.mix.set_solo(band, solo)
+    }
+
+    //processes one host block - input and output need a slice per channel, all the same length
+    //blocks longer than max_block_size are split up
+    pub fn process(&mut self, input: &[&[f32]], output: &mut [&mut [f32]]) -> Result<(), NessError> {
+        if input.len() != self.num_channels || output.len() != self.num_channels {
+            return Err(NessError::invalid("channels", (input.len(), output.len()), self.num_channels.to_string()));
+        }
+        let frames = input[0].len();
+        if input.iter().any(|chan| chan.len() != frames) || output.iter().any(|chan| chan.len() != frames) {
+            return Err(NessError::invalid("block length", frames, "the same length for every input and output channel"));
+        }
+        let mut done = 0;
+        while done < frames {
+            let block = (frames - done).min(self.max_block_size);
+            self.process_block(input, output, done, block);
+            done += block;
+        }
+        Ok(())
+    }
+
+    //returns the work the bands did for the block, in samples and bins touched
+    fn process_block(&mut self, input: &[&[f32]], output: &mut [&mut [f32]], offset: usize, block: usize) -> usize {
+        let in_len = self.input[0].len();
+        for (ring, chan) in self.input.iter_mut().zip(input.iter()) {
+            for i in 0..block {
+                ring[(self.time + i) % in_len] = chan[offset + i] as f64;
+            }
+        }
+        let end = self.time + block;
+        self.follow_input(end);
+
+        let context = BlockContext {
+            latency: self.latency,
+            read_offset: self.read_offset,
+            max_win_size: self.max_win_size,
+            input: &self.input,
+        };
+        //a band whose frame is heard in this block finishes it whatever the budget
+        let carried = self.overrun;
+        let mut spent = carried;
+        for (iter, band) in self.bands.iter_mut().enumerate() {
+            while band.deadline(self.latency) <= end {
+                spent += band.step(&context, self.mix.gain(iter), &mut self.output);
+            }
+        }
+        //the rest of the budget goes to the frame that is heard soonest, out of those whose input has arrived
+        //the last step can run past the budget, and the next callbacks make up for it
+        let budget = (self.work_rate * block as f64 * WORK_HEADROOM).ceil() as usize;
+        let latency = self.latency;
+        while spent < budget {
+            let next = self
+                .bands
+                .iter()
+                .enumerate()
+                .filter(|(_, band)| band.release(latency) < end)
+                .min_by_key(|(_, band)| band.due(latency))
+                .map(|(iter, _)| iter);
+            match next {
+                Some(iter) => spent += self.bands[iter].step(&context, self.mix.gain(iter), &mut self.output),
+                None => break,
+            }
+        }
+        self.overrun = spent.saturating_sub(budget);
+
+        let out_len = self.output[0].len();
+        for (ring, chan) in self.output.iter_mut().zip(output.iter_mut()) {
+            for i in 0..block {
+                let index = (self.time + i) % out_len;
+                chan[offset + i] = ring[index] as f32;
+                ring[index] = 0.0;
+            }
+        }
+        self.time = end;
+        spent - carried
+    }
+
+    //the read position runs at 1/dur_mult of real time, so it falls further behind the live input every block
+    //once the oldest input the slices still need is about to be overwritten, jump forward so the read position is latency samples behind again
+    fn follow_input(&mut self, end: usize) {
+        let max_win_size = self.max_win_size as f64;
+        let center = self.read_offset + (end as f64 - self.latency as f64) / self.dur_mult + max_win_size / 2.0;
+        let lag = end as f64 - center;
+        let capacity = self.input[0].len() as f64;
+        if lag + max_win_size / 2.0 + 2.0 * self.max_block_size as f64 > capacity {
+            self.read_offset += lag - self.latency as f64;
+        }
+    }
+}
+
+impl RealtimeBand {
+    //how many steps it takes to randomize the bins of one frame
+    fn random_steps(&self) -> usize {
+        (self.half_win_len - 1).div_ceil(STEP_SIZE)
+    }
+
+    //how many steps it takes to go over half a frame, which the window and the crossfade each do once per subslice
+    fn range_steps(&self) -> usize {
+        self.half_win_len.div_ceil(STEP_SIZE)
+    }
+
+    //every randomized frame is random_steps plus one for the ifft and correlation, then each subslice is finished
+    fn loop_steps(&self) -> usize {
+        self.num_ffts * (self.random_steps() + 1) + 2 * self.range_steps()
+    }
+
+    //the first step reads and ffts the input
+    fn total_steps(&self) -> usize {
+        1 + self.loops * self.loop_steps()
+    }
+
+    fn step_at(&self, step: usize) -> Step {
+        if step == 0 {
+            return Step::Forward;
+        }
+        let random_steps = self.random_steps();
+        let search_steps = self.num_ffts * (random_steps + 1);
+        let sub = (step - 1) / self.loop_steps();
+        let sub_step = (step - 1) % self.loop_steps();
+        let range = |part: usize| part * STEP_SIZE..((part + 1) * STEP_SIZE).min(self.half_win_len);
+        if sub_step < search_steps {
+            let part = sub_step % (random_steps + 1);
+            if part < random_steps {
+                let first = 1 + part * STEP_SIZE;
+                let bins = first..(first + STEP_SIZE).min(self.half_win_len);
+                Step::Randomize { sub, count: sub_step / (random_steps + 1), bins }
+            } else {
+                Step::Inverse { sub }
+            }
+        } else {
+            let part = sub_step - search_steps;
+            if part < self.range_steps() {
+                Step::Window { range: range(part) }
+            } else {
+                Step::Crossfade { sub, range: range(part - self.range_steps()) }
+            }
+        }
+    }
+
+    //what a step costs for each channel, in samples and bins touched
+    fn work(&self, step: &Step) -> usize {
+        match step {
+            Step::Forward | Step::Inverse { .. } => self.win_len,
+            Step::Randomize { bins, .. } => bins.len(),
+            Step::Window { range } | Step::Crossfade { range, .. } => range.len(),
+        }
+    }
+
+    fn frame_work(&self) -> usize {
+        (0..self.total_steps()).map(|step| self.work(&self.step_at(step))).sum()
+    }
+
+    //the sample the frame being worked on is first heard at
+    fn deadline(&self, latency: usize) -> usize {
+        self.frame * self.half_win_len + latency
+    }
+
+    //the frame's work is spread over the half window before this
+    fn due(&self, latency: usize) -> usize {
+        self.deadline(latency) - self.phase
+    }
+
+    //the frame can't be started until all of its input has arrived
+    fn release(&self, latency: usize) -> usize {
+        self.due(latency).saturating_sub(self.half_win_len)
+    }
+
+    //does the next step of the frame and returns its work
+    fn step(&mut self, context: &BlockContext, mix_gain: f64, output: &mut [Vec<f64>]) -> usize {
+        let step = self.step_at(self.step);
+        let work = self.work(&step) * self.channels.len();
+        self.do_step(step, context, mix_gain, output);
+        self.step += 1;
+        if self.step == self.total_steps() {
+            self.frame += 1;
+            self.step = 0;
+        }
+        work
+    }
+
+    fn do_step(&mut self, step: Step, context: &BlockContext, mix_gain: f64, output: &mut [Vec<f64>]) {
+        let half_win_len = self.half_win_len;
+        match step {
+            Step::Forward => {
+                //the same read point as process_sliced_chunk, but counted from the start of the stream
+                let start = (context.read_offset + self.hop * self.frame as f64) as usize + (context.max_win_size / 2 - half_win_len);
+                for (chan, ring) in self.channels.iter_mut().zip(context.input.iter()) {
+                    let in_len = ring.len();
+                    let FftScratch { part, spectrum, scratch, .. } = &mut chan.bufs;
+                    for (i, (sample, win)) in part.iter_mut().zip(self.in_win.iter()).enumerate() {
+                        *sample = ring[(start + i) % in_len] * win;
+                    }
+                    //the buffers are made by the planner, so the sizes always match
+                    let _ = self.fft.process_with_scratch(part, spectrum, scratch);
+                }
+            }
+            Step::Randomize { sub, count, bins } => {
+                for chan in self.channels.iter_mut() {
+                    if count == 0 && bins.start == 1 {
+                        chan.search = CorrelationSearch::new();
+                    }
+                    let FftScratch { spectrum, spectrum_out, .. } = &mut chan.bufs;
+                    randomize_bins(spectrum, spectrum_out, &self.filters[sub], self.filter_on, &mut chan.rng, bins.clone());
+                }
+            }
+            Step::Inverse { sub } => {
+                //the randomized frame is kept if it correlates better with the last frame than the ones before it
+                for chan in self.channels.iter_mut() {
+                    let last_frame = &chan.last_frame[sub * half_win_len..(sub + 1) * half_win_len];
+                    let FftScratch { spectrum_out, out_frame, scratch, best_frame, .. } = &mut chan.bufs;
+                    let _ = inverse_fft(&*self.ifft, spectrum_out, out_frame, scratch);
+                    chan.search.offer(last_frame, out_frame, best_frame);
+                }
+            }
+            Step::Window { range } => {
+                for chan in self.channels.iter_mut() {
+                    let FftScratch { out_frame, best_frame, ness_window, .. } = &mut chan.bufs;
+                    if range.start == 0 {
+                        chan.search.settle(out_frame, best_frame);
+                    }
+                    chan.search.orient(&mut best_frame[range.clone()]);
+                    chan.search.orient(&mut best_frame[half_win_len + range.start..half_win_len + range.end]);
+                    fill_ness_window(ness_window, self.win_len, chan.search.corr_abs(), range.clone());
+                }
+            }
+            Step::Crossfade { sub, range } => {
+                let out_spot = self.frame * half_win_len + context.latency + range.start;
+                for (chan, ring) in self.channels.iter_mut().zip(output.iter_mut()) {
+                    let last_frame = &mut chan.last_frame[sub * half_win_len..(sub + 1) * half_win_len];
+                    let FftScratch { best_frame, ness_window, frame, .. } = &mut chan.bufs;
+                    crossfade(frame, best_frame, last_frame, ness_window, range.clone());
+                    let out_len = ring.len();
+                    for (i, sample) in frame[range.clone()].iter().enumerate() {
+                        ring[(out_spot + i) % out_len] += sample / self.win_len as f64 * self.gain * mix_gain;
+                    }
+                    last_frame[range.clone()].copy_from_slice(&best_frame[half_win_len + range.start..half_win_len + range.end]);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(rt: &mut RealtimeStretcher, input: &[f32], block: usize, mut on_block: impl FnMut(usize)) -> Vec<f32> {
+        let mut output = vec![0.0; input.len()];
+        for (offset, chunk) in input.chunks(block).enumerate() {
+            let out = &mut output[offset * block..offset * block + chunk.len()];
+            on_block(rt.process_block(&[chunk], &mut [out], 0, chunk.len()));
+        }
+        output
+    }
+
+    #[test]
+    fn small_blocks_stay_within_the_work_budget() {
+        let config = NessConfig::new(4.0).seed(7);
+        let mut rt = RealtimeStretcher::new(&config, 44100, 1, 64).unwrap();
+        let frames = rt.latency() + 2 * rt.max_win_size;
+        let input: Vec<f32> = (0..frames).map(|i| ((i as f32 * 0.031).sin() + (i as f32 * 0.0047).sin()) * 0.4).collect();
+
+        //a block can only go over its budget by the one step that was started inside it
+        let budget = (rt.work_rate * 64.0 * WORK_HEADROOM).ceil() as usize;
+        let mut most = 0;
+        let output = render(&mut rt, &input, 64, |work| most = most.max(work));
+        assert!(most <= budget + rt.max_win_size, "a block did {} work with a budget of {}", most, budget);
+        assert!(output[rt.latency() + rt.max_win_size..].iter().any(|sample| *sample != 0.0));
+
+        //when the steps run makes no difference to what they make
+        let mut rt = RealtimeStretcher::new(&config, 44100, 1, 1024).unwrap();
+        assert_eq!(render(&mut rt, &input, 1024, |_| {}), output);
+    }
+}
@@ -0,0 +1,612 @@
+use crate::freeze::FreezeTarget;
+use crate::{config_for_sample_rate, process_chunk_into, InputPosition, NessConfig, NessError, NessStruct, StretchCurve};
+
+//streaming front end for NessStruct
+//audio goes in through push in blocks of any size and stretched audio comes out through pull
+//the stretcher keeps track of the chunk points and only holds on to the input it still needs
+pub struct NessStretcher {
+    ness_struct: NessStruct,
+    dur_mult: f64,
+    verbose: bool,
+    //the input that has not been read past yet, one vector per channel
+    //input[chan][0] is sample number input_start of the whole stream
+    input: Vec<Vec<f64>>,
+    input_start: usize,
+    input_len: usize,
+    //the frames of context at the start of the input, which are read but not stretched
+    context_len: usize,
+    finished: bool,
+    //the next chunk to process
+    chunk: usize,
+    //a cap on the number of chunks, from num_output_blocks
+    max_chunks: Option<usize>,
+    //with a stretch curve the chunk points are added up as the stretch changes, so the stretcher keeps
+    //the input position of the next chunk and, once the input is finished, how many frames the curve makes
+    curve: Option<StretchCurve>,
+    curve_point: f64,
+    curve_frames: Option<usize>,
+    //how many chunks' worth of reading the freezes have held back, which pushes every chunk point and the end of the stream back
+    frozen_chunks: f64,
+    //a freeze_at on its way to its point, and the chunk point it holds once it is there
+    freeze_target: Option<FreezeTarget>,
+    held_point: Option<usize>,
+    //how much of ness_struct.stored_chunk has already been pulled
+    out_pos: usize,
+    out_len: usize,
+    //an exact length for the whole output from set_output_len, which replaces the stretched length of the input
+    out_limit: Option<usize>,
+    //the output runs on this many frames past the stretched input and fades out over them
+    tail_len: usize,
+    //the shortest fade at the end of the output
+    end_fade: usize,
+}
+
+//the fade at the end of the output, long enough not to click
+const END_FADE_SECONDS: f64 = 0.05;
+
+impl NessStretcher {
+    pub fn new(config: &NessConfig, sample_rate: u32, num_channels: usize) -> Result<NessStretcher, NessError> {
+        let config = config_for_sample_rate(config, sample_rate)?;
+        let ness_struct = NessStruct::new(&config, sample_rate, num_channels)?;
+
+        Ok(NessStretcher {
+            ness_struct,
+            dur_mult: config.dur_mult,
+            verbose: config.verbose,
+            input: vec![vec![0.0; 0]; num_channels],
+            input_start: 0,
+            input_len: 0,
+            context_len: 0,
+            finished: false,
+            chunk: 0,
+            max_chunks: config.num_output_blocks,
+            curve: config.stretch_curve.clone(),
+            curve_point: 0.0,
+            curve_frames: None,
+            frozen_chunks: 0.0,
+            freeze_target: None,
+            held_point: None,
+            out_pos: 0,
+            out_len: 0,
+            out_limit: None,
+            tail_len: config.tail.map_or(0, |tail| tail.frames(sample_rate)),
+            end_fade: (END_FADE_SECONDS * sample_rate as f64) as usize,
+        })
+    }
+
+    pub fn num_channels(&self) -> usize {
+        self.ness_struct.num_channels
+    }
+
+    pub fn max_win_size(&self) -> usize {
+        self.ness_struct.max_win_size
+    }
+
+    //holds the read position where it is, gliding to a stop over ramp_chunks chunks, and keeps making new frames of
+    //that moment for as long as output is pulled - the stream doesn't end while it is frozen
+    pub fn freeze(&mut self, ramp_chunks: usize) {
+        self.freeze_target = None;
+        self.ness_struct.freeze(ramp_chunks);
+    }
+
+    //holds the read position on the input sample at position, counted from the start of the stretched input
+    //it reads on at the stretch until it is ramp_chunks chunks away, then glides to a stop exactly on the sample -
+    //0 jumps straight there on the next chunk
+    pub fn freeze_at(&mut self, position: InputPosition, ramp_chunks: usize) -> Result<(), NessError> {
+        if let InputPosition::Seconds(seconds) = position {
+            if !seconds.is_finite() || seconds < 0.0 {
+                return Err(NessError::invalid("freeze_at", seconds, "a finite number of seconds >= 0.0"));
+            }
+        }
+        let sample = position.frames(self.ness_struct.sample_rate);
+        if sample < self.read_position() {
+            return Err(NessError::invalid("freeze_at", position, format!("a position at or after the read position ({})", self.read_position())));
+        }
+        if self.finished && sample >= self.stretch_len() {
+            return Err(NessError::invalid("freeze_at", position, format!("a position before the end of the input ({} samples)", self.stretch_len())));
+        }
+        let point = sample + self.context_len - self.max_win_size() / 2;
+        self.held_point = None;
+        self.freeze_target = Some(FreezeTarget::new(point as f64, ramp_chunks));
+        Ok(())
+    }
+
+    //lets the read position go again, gliding back up to the stretch over ramp_chunks chunks
+    pub fn unfreeze(&mut self, ramp_chunks: usize) {
+        self.freeze_target = None;
+        self.held_point = None;
+        self.ness_struct.unfreeze(ramp_chunks);
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.ness_struct.is_frozen()
+    }
+
+    //the input sample the next chunk is centered on, which is where a freeze holds
+    //it is counted from the start of the stretched input, after any context
+    pub fn read_position(&self) -> usize {
+        (self.read_point() + self.max_win_size() / 2).saturating_sub(self.context_len)
+    }
+
+    //the window and frequency range of every band, from the highest to the lowest
+    pub fn win_lens(&self) -> &[usize] {
+        &self.ness_struct.win_lens
+    }
+
+    pub fn freq_ranges(&self) -> &[(f64, f64)] {
+        &self.ness_struct.freq_ranges
+    }
+
+    //the mix of the bands, numbered from 0 for the highest - changes ramp in across the next chunk
+    pub fn set_band_gain(&mut self, band: usize, gain_db: f64) -> Result<(), NessError> {
+        self.ness_struct.set_band_gain(band, gain_db)
+    }
+
+    pub fn set_band_mute(&mut self, band: usize, mute: bool) -> Result<(), NessError> {
+        self.ness_struct.set_band_mute(band, mute)
+    }
+
+    pub fn set_band_solo(&mut self, band: usize, solo: bool) -> Result<(), NessError> {
+        self.ness_struct.set_band_solo(band, solo)
+    }
+
+    //adds a block of audio, one slice per channel
+    //all channels have to be the same length, but the length can be anything
+    pub fn push(&mut self, input: &[&[f32]]) -> Result<(), NessError> {
+        if self.finished {
+            return Err(NessError::invalid("push", "after finish", "no more input once finish has been called"));
+        }
+        if input.len() != self.num_channels() {
+            return Err(NessError::invalid("input channels", input.len(), self.num_channels().to_string()));
+        }
+        let frames = input[0].len();
+        if let Some(chan) = input.iter().find(|chan| chan.len() != frames) {
+            return Err(NessError::invalid("input block length", chan.len(), format!("{} (the length of the first channel)", frames)));
+        }
+        for (buffer, chan) in self.input.iter_mut().zip(input.iter()) {
+            buffer.extend(chan.iter().map(|x| *x as f64));
+        }
+        self.input_len += frames;
+        Ok(())
+    }
+
+    //audio from before the start of the stretch, which the first windows read but which isn't stretched or counted in the length
+    //the last half max_win_size frames are kept, with silence before them if there are fewer, and the first frames
+    //are centered on the first pushed sample - this has to come before any push
+    //empty context is all silence, which is how a whole stream starts on its first sample instead of half a max_win_size in
+    pub fn push_context(&mut self, context: &[&[f32]]) -> Result<(), NessError> {
+        if self.input_len > 0 {
+            return Err(NessError::invalid("push_context", "after push", "context before any input"));
+        }
+        if context.len() != self.num_channels() {
+            return Err(NessError::invalid("context channels", context.len(), self.num_channels().to_string()));
+        }
+        let half = self.max_win_size() / 2;
+        for (buffer, chan) in self.input.iter_mut().zip(context.iter()) {
+            let kept = &chan[chan.len().saturating_sub(half)..];
+            buffer.resize(half - kept.len(), 0.0);
+            buffer.extend(kept.iter().map(|x| *x as f64));
+        }
+        self.input_len = half;
+        self.context_len = half;
+        Ok(())
+    }
+
+    //tells the stretcher there is no more input, so the last chunks can be made with silence after the end of the input
+    pub fn finish(&mut self) {
+        self.finished = true;
+        if let Some(curve) = &self.curve {
+            //walks the curve to the end of the input, adding up the output each chunk makes
+            //a stretch the curve can't use is counted as 1 here and reported when its chunk is processed
+            let max_win_size = self.max_win_size() as f64;
+            let stretch_len = self.stretch_len() as f64;
+            let max_frames = self.max_chunks.map_or(f64::INFINITY, |max_chunks| (max_chunks * self.max_win_size()) as f64);
+            let mut point = 0.0;
+            let mut frames = 0.0;
+            while point < stretch_len && frames < max_frames {
+                let dur_mult = curve.dur_mult_at(self.curve_time(point));
+                let dur_mult = if dur_mult.is_finite() { dur_mult.max(1.0) } else { 1.0 };
+                //the last chunk only makes output for the input that is left
+                let step = (max_win_size / dur_mult).min(stretch_len - point);
+                point += step;
+                frames += step * dur_mult;
+            }
+            self.curve_frames = Some(frames.round() as usize);
+        }
+    }
+
+    //the time in the stretched input, after the context, that a chunk reading from point is centered on
+    fn curve_time(&self, point: f64) -> f64 {
+        let center = point + (self.max_win_size() / 2) as f64 - self.context_len as f64;
+        center / self.ness_struct.sample_rate as f64
+    }
+
+    //the number of frames the whole stream makes, known once finish has been called
+    //it is the stretched length of the input, round(input frames * dur_mult), plus the tail and the time spent frozen
+    pub fn output_len(&self) -> Option<usize> {
+        if let Some(out_limit) = self.out_limit {
+            return Some(out_limit);
+        }
+        if !self.finished {
+            return None;
+        }
+        let stretched = match &self.curve {
+            Some(_) => self.curve_frames.unwrap_or(0),
+            None => (self.stretch_len() as f64 * self.dur_mult).round() as usize,
+        };
+        let frozen = (self.frozen_chunks * self.max_win_size() as f64).round() as usize;
+        Some(stretched + frozen + self.tail_len)
+    }
+
+    //the number of chunks the whole stream will make, known once finish has been called
+    pub fn num_chunks(&self) -> Option<usize> {
+        let chunks = self.output_len().map(|output_len| output_len.div_ceil(self.max_win_size()));
+        match (chunks, self.max_chunks) {
+            (Some(chunks), Some(max_chunks)) => Some(chunks.min(max_chunks)),
+            (chunks, None) => chunks,
+            (None, max_chunks) => max_chunks,
+        }
+    }
+
+    //makes the whole output exactly frames long, in place of the stretched length of the input and the tail
+    //num_output_blocks still caps it
+    pub fn set_output_len(&mut self, frames: usize) {
+        self.out_limit = Some(frames);
+    }
+
+    //how many frames at the end of the output fade out - the whole tail, or a short fade so the cut doesn't click
+    fn fade_len(&self, output_len: usize) -> usize {
+        let fade_len = if self.out_limit.is_some() { self.end_fade } else { self.end_fade.max(self.tail_len) };
+        fade_len.min(output_len)
+    }
+
+    //the length of the input that is stretched, without the context
+    fn stretch_len(&self) -> usize {
+        self.input_len - self.context_len
+    }
+
+    //the chunk that will be processed next
+    pub fn chunk(&self) -> usize {
+        self.chunk
+    }
+
+    //true once every chunk has been made and pulled
+    pub fn is_done(&self) -> bool {
+        self.out_pos == self.out_len && self.num_chunks().is_some_and(|num_chunks| self.chunk >= num_chunks)
+    }
+
+    //fills the output slices with stretched audio and returns how many frames were written
+    //returns less than the output length when more input is needed (or the stream is done)
+    pub fn pull(&mut self, output: &mut [&mut [f32]]) -> Result<usize, NessError> {
+        self.pull_into(output, None)
+    }
+
+    //pull that also fills stems[band][chan] with each band's part of the output, before the mix
+    //the stems need the same length as the output
+    pub fn pull_stems(&mut self, output: &mut [&mut [f32]], stems: &mut [Vec<&mut [f32]>]) -> Result<usize, NessError> {
+        if stems.len() != self.win_lens().len() {
+            return Err(NessError::invalid("stems", stems.len(), format!("{} (one per band)", self.win_lens().len())));
+        }
+        if let Some(band) = stems.iter().find(|band| band.len() != self.num_channels()) {
+            return Err(NessError::invalid("stem channels", band.len(), self.num_channels().to_string()));
+        }
+        self.pull_into(output, Some(stems))
+    }
+
+    fn pull_into(&mut self, output: &mut [&mut [f32]], mut stems: Option<&mut [Vec<&mut [f32]>]>) -> Result<usize, NessError> {
+        if output.len() != self.num_channels() {
+            return Err(NessError::invalid("output channels", output.len(), self.num_channels().to_string()));
+        }
+        let mut frames = output.iter().map(|chan| chan.len()).min().unwrap_or(0);
+        if let Some(stems) = &stems {
+            frames = stems.iter().flatten().map(|chan| chan.len()).fold(frames, usize::min);
+        }
+        let mut written = 0;
+        while written < frames {
+            if self.out_pos == self.out_len && !self.next_chunk()? {
+                break;
+            }
+            let to_copy = (frames - written).min(self.out_len - self.out_pos);
+            for (chan, out) in output.iter_mut().enumerate() {
+                let stored = &self.ness_struct.stored_chunk[chan][self.out_pos..self.out_pos + to_copy];
+                for (o, s) in out[written..written + to_copy].iter_mut().zip(stored.iter()) {
+                    *o = *s as f32;
+                }
+                self.fade_end(&mut out[written..written + to_copy]);
+            }
+            if let Some(stems) = &mut stems {
+                for (band, stem) in stems.iter_mut().enumerate() {
+                    for (chan, out) in stem.iter_mut().enumerate() {
+                        if let Some(stored) = self.ness_struct.band_chunk(band, chan) {
+                            for (o, s) in out[written..written + to_copy].iter_mut().zip(stored[self.out_pos..].iter()) {
+                                *o = *s as f32;
+                            }
+                        }
+                        self.fade_end(&mut out[written..written + to_copy]);
+                    }
+                }
+            }
+            self.out_pos += to_copy;
+            written += to_copy;
+        }
+        Ok(written)
+    }
+
+    //fades out the frames of the current chunk from out_pos that fall in the fade at the end of the output
+    fn fade_end(&self, out: &mut [f32]) {
+        let Some(output_len) = self.output_len() else {
+            return;
+        };
+        let fade_len = self.fade_len(output_len);
+        //the chunk has already been counted, so it starts a chunk back
+        let start = (self.chunk - 1) * self.max_win_size() + self.out_pos;
+        let fade_start = output_len - fade_len;
+        for (iter, sample) in out.iter_mut().enumerate() {
+            let frame = start + iter;
+            if frame >= fade_start {
+                *sample *= output_len.saturating_sub(frame) as f32 / fade_len as f32;
+            }
+        }
+    }
+
+    //the point in the input stream where a chunk starts reading, for a band stretching by dur_mult
+    fn chunk_point(&self, chunk: usize, dur_mult: f64) -> usize {
+        ((chunk as f64 - self.frozen_chunks) * self.max_win_size() as f64 / dur_mult) as usize
+    }
+
+    //the point in the input stream where the next chunk starts reading, for the bands that follow dur_mult or the curve
+    fn read_point(&self) -> usize {
+        if let Some(held_point) = self.held_point {
+            return held_point;
+        }
+        match &self.curve {
+            Some(_) => self.curve_point as usize,
+            None => self.chunk_point(self.chunk, self.dur_mult),
+        }
+    }
+
+    //the read point before it is rounded to a sample, which a freeze_at glides in on
+    fn exact_read_point(&self) -> f64 {
+        match &self.curve {
+            Some(_) => self.curve_point,
+            None => (self.chunk as f64 - self.frozen_chunks) * self.max_win_size() as f64 / self.dur_mult,
+        }
+    }
+
+    //the chunk point of every input the next chunk reads - in_chunk and then each band that has its own stretch
+    //the ones stretching less are further along
+    fn chunk_points(&self) -> impl Iterator<Item = usize> + '_ {
+        let band_points = self.ness_struct.band_dur_mults.iter().zip(self.ness_struct.band_in_chunks.iter())
+            .filter(|(_, band_in_chunk)| !band_in_chunk.is_empty())
+            .map(move |(band_dur_mult, _)| self.chunk_point(self.chunk, *band_dur_mult));
+        std::iter::once(self.read_point()).chain(band_points)
+    }
+
+    //copies a chunk of input starting offset frames into the buffered input, with silence past the end of the input
+    fn fill_chunk(input: &[Vec<f64>], offset: usize, in_chunk: &mut [Vec<f64>]) {
+        for (buffer, chunk) in input.iter().zip(in_chunk.iter_mut()) {
+            for (point, sample) in (offset..).zip(chunk.iter_mut()) {
+                *sample = buffer.get(point).copied().unwrap_or(0.0);
+            }
+        }
+    }
+
+    //processes the next chunk if there is enough input for it
+    fn next_chunk(&mut self) -> Result<bool, NessError> {
+        if let Some(num_chunks) = self.num_chunks() {
+            if self.chunk >= num_chunks {
+                return Ok(false);
+            }
+        }
+        let max_win_size = self.max_win_size();
+        //each chunk reads 2*max_win_size samples from its chunk points, or until the end of the stream
+        let mut last_point = self.chunk_points().max().unwrap_or(0);
+        //a freeze_at with nothing left to glide jumps straight to its point
+        if let Some(target) = self.freeze_target.as_ref().filter(|target| target.jumps()) {
+            last_point = last_point.max(target.point as usize);
+        }
+        if !self.finished && self.input_len < last_point + max_win_size * 2 {
+            return Ok(false);
+        }
+
+        if self.verbose && self.chunk.is_multiple_of(25) {
+            match self.num_chunks() {
+                Some(num_chunks) => println!("chunk {} of {}", self.chunk, num_chunks),
+                None => println!("chunk {}", self.chunk),
+            }
+        }
+
+        //the curve sets the stretch of the whole chunk from the input it is centered on
+        let mut curve_dur_mult = 1.0;
+        if let Some(curve) = &self.curve {
+            curve_dur_mult = curve.dur_mult_at(self.curve_time(self.curve_point));
+            if !curve_dur_mult.is_finite() || curve_dur_mult < 1.0 {
+                return Err(NessError::invalid("stretch_curve", curve_dur_mult, "stretches >= 1.0"));
+            }
+            self.ness_struct.set_dur_mult(curve_dur_mult)?;
+        }
+
+        //a freeze_at sets the speed of every chunk until the read position is on its point, and then holds it there
+        if let Some(target_point) = self.freeze_target.as_ref().map(|target| target.point) {
+            let stretch = if self.curve.is_some() { curve_dur_mult } else { self.dur_mult };
+            let distance = target_point - self.exact_read_point();
+            let speed = self.freeze_target.as_mut().and_then(|target| target.next_speed(distance, max_win_size as f64 / stretch));
+            match speed {
+                Some(speed) => self.ness_struct.set_speed(speed),
+                None => {
+                    self.freeze_target = None;
+                    self.held_point = Some(target_point as usize);
+                    self.ness_struct.set_speed(0.0);
+                }
+            }
+        }
+
+        let chunk_point = self.read_point();
+        Self::fill_chunk(&self.input, chunk_point - self.input_start, &mut self.ness_struct.in_chunk);
+        for band in 0..self.ness_struct.band_in_chunks.len() {
+            if !self.ness_struct.band_in_chunks[band].is_empty() {
+                let band_point = self.chunk_point(self.chunk, self.ness_struct.band_dur_mults[band]);
+                Self::fill_chunk(&self.input, band_point - self.input_start, &mut self.ness_struct.band_in_chunks[band]);
+            }
+        }
+
+        //stored_chunk is moved out so the chunk can be written straight into it
+        let mut stored_chunk = std::mem::take(&mut self.ness_struct.stored_chunk);
+        let result = process_chunk_into(&mut self.ness_struct, &mut stored_chunk);
+        self.ness_struct.stored_chunk = stored_chunk;
+        result?;
+        //a chunk read at a slower speed moves the chunk points on less, and the time it holds back is added to
+        //the output before the chunk is cut, so a freeze after finish still makes whole chunks
+        let speed = self.ness_struct.chunk_speed();
+        self.frozen_chunks += 1.0 - speed;
+        self.curve_point += max_win_size as f64 * speed / curve_dur_mult;
+        self.out_pos = 0;
+        self.out_len = max_win_size;
+        //the last chunk is cut to the length of the output
+        if let Some(output_len) = self.output_len() {
+            self.out_len = max_win_size.min(output_len.saturating_sub(self.chunk * max_win_size));
+        }
+        self.chunk += 1;
+
+        //drop the input that no later chunk will read, which is everything before the band that stretches the most
+        let next_point = self.chunk_points().min().unwrap_or(0).min(self.input_len);
+        let consumed = next_point - self.input_start;
+        for buffer in self.input.iter_mut() {
+            buffer.drain(..consumed);
+        }
+        self.input_start = next_point;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::OutputLength;
+
+    //a sine in every channel, each an octave above the last
+    fn sine(frames: usize, num_channels: usize) -> Vec<Vec<f32>> {
+        (0..num_channels)
+            .map(|chan| (0..frames).map(|i| 0.5 * (i as f32 * 440.0 * (chan + 1) as f32 * 2.0 * std::f32::consts::PI / 44100.0).sin()).collect())
+            .collect()
+    }
+
+    //stretches the whole input the way process_file does, starting on its first frame
+    fn render(config: &NessConfig, input: &[Vec<f32>]) -> Vec<Vec<f32>> {
+        let num_channels = input.len();
+        let mut stretcher = NessStretcher::new(config, 44100, num_channels).unwrap();
+        stretcher.push_context(&vec![&[][..]; num_channels]).unwrap();
+        let block: Vec<&[f32]> = input.iter().map(|chan| &chan[..]).collect();
+        stretcher.push(&block).unwrap();
+        stretcher.finish();
+
+        let mut output = vec![vec![0.0_f32; 0]; num_channels];
+        let mut out_block = vec![vec![0.0_f32; 4096]; num_channels];
+        while !stretcher.is_done() {
+            let mut block: Vec<&mut [f32]> = out_block.iter_mut().map(|chan| &mut chan[..]).collect();
+            let frames = stretcher.pull(&mut block).unwrap();
+            for chan in 0..num_channels {
+                output[chan].extend_from_slice(&out_block[chan][..frames]);
+            }
+        }
+        output
+    }
+
+    fn rms(samples: &[f32]) -> f64 {
+        let sum: f64 = samples.iter().map(|x| (*x as f64) * (*x as f64)).sum();
+        (sum / samples.len() as f64).sqrt()
+    }
+
+    #[test]
+    fn output_is_the_rounded_stretched_length() {
+        let input = sine(22051, 1);
+        let output = render(&NessConfig::new(1.5).num_slices(3).seed(1).verbose(false), &input);
+        assert_eq!(output[0].len(), 33077);
+        let output = render(&NessConfig::new(2.0).num_slices(3).seed(1).verbose(false), &input);
+        assert_eq!(output[0].len(), 44102);
+    }
+
+    #[test]
+    fn a_curve_renders_the_length_finish_works_out() {
+        let input = sine(88200, 1);
+        let config = NessConfig::new(1.0).stretch_curve(StretchCurve::breakpoints(vec![(0.5, 2.0), (1.5, 4.0)])).num_slices(3).seed(1).verbose(false);
+        let mut stretcher = NessStretcher::new(&config, 44100, 1).unwrap();
+        stretcher.push_context(&[&[]]).unwrap();
+        stretcher.push(&[&input[0]]).unwrap();
+        stretcher.finish();
+        let output_len = stretcher.output_len().unwrap();
+        assert!(output_len > 2 * 88200 && output_len < 4 * 88200);
+        assert_eq!(render(&config, &input)[0].len(), output_len);
+    }
+
+    #[test]
+    fn tail_adds_its_length_and_carries_the_decay() {
+        let input = sine(22050, 1);
+        let config = NessConfig::new(2.0).num_slices(3).seed(1).verbose(false);
+        let output = render(&config.clone().tail(OutputLength::Samples(11025)), &input);
+        assert_eq!(output[0].len(), 44100 + 11025);
+        //the first half of the tail still holds the sound of the last windows
+        assert!(rms(&output[0][44100..49612]) > 0.01);
+        //and the very end has faded out
+        assert!(output[0][output[0].len() - 1].abs() < 1e-3);
+    }
+
+    #[test]
+    fn freezing_a_finished_stream_makes_whole_chunks() {
+        let input = sine(44100, 1);
+        let mut stretcher = NessStretcher::new(&NessConfig::new(2.0).num_slices(3).seed(1).verbose(false), 44100, 1).unwrap();
+        stretcher.push_context(&[&[]]).unwrap();
+        stretcher.push(&[&input[0]]).unwrap();
+        stretcher.finish();
+        let max_win_size = stretcher.max_win_size();
+        let mut out_block = vec![0.0_f32; max_win_size];
+        assert_eq!(stretcher.pull(&mut [&mut out_block]).unwrap(), max_win_size);
+        stretcher.freeze(0);
+        //every pull of a chunk's length is exactly one more chunk, however long the freeze goes on
+        for chunk in 2..6 {
+            assert_eq!(stretcher.pull(&mut [&mut out_block]).unwrap(), max_win_size);
+            assert_eq!(stretcher.chunk(), chunk);
+        }
+        //and once it lets go the rest of the input comes out and the stream ends on its length
+        stretcher.unfreeze(0);
+        let mut pulled = stretcher.chunk() * max_win_size;
+        while !stretcher.is_done() {
+            pulled += stretcher.pull(&mut [&mut out_block]).unwrap();
+        }
+        assert_eq!(Some(pulled), stretcher.output_len());
+    }
+
+    #[test]
+    fn freeze_at_glides_to_a_stop_on_its_sample() {
+        let input = sine(44100 * 3, 1);
+        let mut stretcher = NessStretcher::new(&NessConfig::new(2.0).num_slices(3).seed(1).verbose(false), 44100, 1).unwrap();
+        stretcher.push_context(&[&[]]).unwrap();
+        stretcher.push(&[&input[0]]).unwrap();
+        stretcher.finish();
+        //after the context the first chunk is centered on the first input sample
+        assert_eq!(stretcher.read_position(), 0);
+        assert!(stretcher.freeze_at(InputPosition::Samples(44100 * 3), 2).is_err());
+        stretcher.freeze_at(InputPosition::Samples(100000), 3).unwrap();
+
+        let mut out_block = vec![0.0_f32; stretcher.max_win_size()];
+        let mut positions = vec![];
+        for _ in 0..8 {
+            stretcher.pull(&mut [&mut out_block]).unwrap();
+            positions.push(stretcher.read_position());
+        }
+        assert!(stretcher.is_frozen());
+        assert_eq!(stretcher.read_position(), 100000);
+        //the read position never goes past the sample on its way there
+        assert!(positions.windows(2).all(|pair| pair[0] <= pair[1] && pair[1] <= 100000));
+        assert!(stretcher.freeze_at(InputPosition::Samples(99999), 0).is_err());
+    }
+
+    #[test]
+    fn seeded_output_does_not_depend_on_the_number_of_threads() {
+        let input = sine(44100, 2);
+        let config = NessConfig::new(2.0).seed(7).verbose(false);
+        let default_threads = render(&config, &input);
+        assert_eq!(render(&config.clone().num_threads(1), &input), default_threads);
+        assert_eq!(render(&config.clone().num_threads(3), &input), default_threads);
+    }
+}
@@ -0,0 +1,311 @@
+use crate::layout::{chunk_size, SliceLayout};
+use crate::{BandSplitMode, ExtremeMode, InputPosition, NessConfig, NessError, OutputLength, StretchCurve, WindowKind};
+
+//the smallest window the lowest slice can have
+pub(crate) const MIN_WIN_SIZE: usize = 256;
+//the largest window a very low crossover can ask for - about 24 seconds at 44.1K
+pub(crate) const MAX_WIN_SIZE: usize = 1 << 20;
+//the smallest window a slice given its own window can have
+pub(crate) const MIN_BAND_WIN_SIZE: usize = 16;
+//each extra correlation candidate is another full ifft per frame, so put a ceiling on it
+pub(crate) const MAX_CORRELATION_FFTS: usize = 100;
+
+//checks the user facing stretch parameters
+//this runs before any file is opened, so a bad setting fails fast and says which parameter is wrong
+pub(crate) fn validate_config(config: &NessConfig) -> Result<(), NessError> {
+    let dur_mult = config.dur_mult;
+    if !dur_mult.is_finite() || dur_mult < 1.0 {
+        return Err(NessError::invalid("dur_mult", dur_mult, "a finite number >= 1.0"));
+    }
+    //a function's stretches can only be checked as they are used
+    if let Some(StretchCurve::Breakpoints(points)) = &config.stretch_curve {
+        if points.is_empty() {
+            return Err(NessError::invalid("stretch_curve", points, "at least one breakpoint"));
+        }
+        if let Some(point) = points.iter().find(|(time, dur_mult)| !time.is_finite() || !dur_mult.is_finite() || *dur_mult < 1.0) {
+            return Err(NessError::invalid("stretch_curve", point, "breakpoints with finite times and stretches >= 1.0"));
+        }
+        for iter in 1..points.len() {
+            if points[iter].0 <= points[iter - 1].0 {
+                return Err(NessError::invalid("stretch_curve", points[iter], "breakpoints in order of time"));
+            }
+        }
+    }
+    if let Some(band_dur_mult) = config.band_dur_mults.iter().flatten().find(|band_dur_mult| !band_dur_mult.is_finite() || **band_dur_mult < 1.0) {
+        return Err(NessError::invalid("band_dur_mults", band_dur_mult, "finite stretches >= 1.0"));
+    }
+    if let ExtremeMode::CorrelationSearch(n) = config.extreme {
+        if n == 0 || n > MAX_CORRELATION_FFTS {
+            return Err(NessError::invalid("extreme", config.extreme, format!("CorrelationSearch(1..={})", MAX_CORRELATION_FFTS)));
+        }
+    }
+    //the upper limit depends on the sample rate, so it is checked with the layout
+    if config.num_slices == Some(0) {
+        return Err(NessError::invalid("num_slices", 0, ">= 1"));
+    }
+    if let Some(crossovers) = &config.crossovers {
+        //whether they are under nyquist is checked with the layout
+        if crossovers.is_empty() {
+            return Err(NessError::invalid("crossovers", crossovers, "at least one frequency (num_slices(1) is the unsliced paulstretch)"));
+        }
+        if let Some(crossover) = crossovers.iter().find(|crossover| !crossover.is_finite() || **crossover <= 0.0) {
+            return Err(NessError::invalid("crossovers", crossover, "frequencies above 0Hz"));
+        }
+        for iter in 1..crossovers.len() {
+            if crossovers[iter..].contains(&crossovers[iter - 1]) {
+                return Err(NessError::invalid("crossovers", crossovers[iter - 1], "every crossover only once"));
+            }
+        }
+    }
+    if let Some(win_lens) = &config.win_lens {
+        //whether there is one per slice is checked with the layout
+        if win_lens.is_empty() {
+            return Err(NessError::invalid("win_lens", win_lens, "at least one window"));
+        }
+        if let Some(win_len) = win_lens.iter().find(|win_len| **win_len % 2 != 0 || **win_len < MIN_BAND_WIN_SIZE || **win_len > MAX_WIN_SIZE) {
+            return Err(NessError::invalid("win_lens", win_len, format!("even windows of {} to {} samples", MIN_BAND_WIN_SIZE, MAX_WIN_SIZE)));
+        }
+    }
+    match config.band_split {
+        BandSplitMode::Overlapping(octaves) if !octaves.is_finite() || octaves < 0.0 => {
+            return Err(NessError::invalid("band_split", config.band_split, "Overlapping(octaves) with a finite number of octaves >= 0.0"));
+        }
+        BandSplitMode::Layered(gain_db) if !gain_db.is_finite() => {
+            return Err(NessError::invalid("band_split", config.band_split, "Layered(gain_db) with a finite gain"));
+        }
+        _ => {}
+    }
+    if let Some(gain_db) = config.band_gains.iter().flatten().find(|gain_db| !gain_db.is_finite()) {
+        return Err(NessError::invalid("band_gains", gain_db, "finite gains in dB"));
+    }
+    if !config.crossover_order.is_finite() || config.crossover_order <= 0.0 {
+        return Err(NessError::invalid("crossover_order", config.crossover_order, "a finite number above 0.0"));
+    }
+    validate_window("window", config.window)?;
+    if let Some(band_windows) = &config.band_windows {
+        //whether there is one per slice is checked with the layout
+        if band_windows.is_empty() {
+            return Err(NessError::invalid("band_windows", band_windows, "at least one window"));
+        }
+        for window in band_windows.iter() {
+            validate_window("band_windows", *window)?;
+        }
+    }
+    //whether the region is inside the file is checked when the file is opened
+    for (name, position) in [("region_start", config.region_start), ("region_end", config.region_end)] {
+        if let Some(InputPosition::Seconds(seconds)) = position {
+            if !seconds.is_finite() || seconds < 0.0 {
+                return Err(NessError::invalid(name, seconds, "a finite number of seconds >= 0.0"));
+            }
+        }
+    }
+    if let Some(OutputLength::Seconds(seconds)) = config.tail {
+        if !seconds.is_finite() || seconds < 0.0 {
+            return Err(NessError::invalid("tail", seconds, "a finite number of seconds >= 0.0"));
+        }
+    }
+    match config.target_duration {
+        Some(OutputLength::Seconds(seconds)) if !seconds.is_finite() || seconds <= 0.0 => {
+            return Err(NessError::invalid("target_duration", seconds, "a finite number of seconds above 0.0"));
+        }
+        Some(OutputLength::Samples(0)) => return Err(NessError::invalid("target_duration", 0, ">= 1 sample")),
+        _ => {}
+    }
+    //a target is the whole length of the output, so nothing else may cut it short or add to it
+    if config.target_duration.is_some() {
+        if let Some(num_output_blocks) = config.num_output_blocks {
+            return Err(NessError::invalid("num_output_blocks", num_output_blocks, "not set when target_duration is set"));
+        }
+        if let Some(tail) = config.tail {
+            return Err(NessError::invalid("tail", tail, "not set when target_duration is set"));
+        }
+    }
+    if config.win_size_divisor == 0 {
+        return Err(NessError::invalid("win_size_divisor", config.win_size_divisor, ">= 1"));
+    }
+    if config.num_output_blocks == Some(0) {
+        return Err(NessError::invalid("num_output_blocks", 0, ">= 1"));
+    }
+    if config.num_threads == Some(0) {
+        return Err(NessError::invalid("num_threads", 0, ">= 1"));
+    }
+    Ok(())
+}
+
+fn validate_window(name: &'static str, window: WindowKind) -> Result<(), NessError> {
+    match window {
+        WindowKind::Kaiser(beta) if !beta.is_finite() || beta < 0.0 => Err(NessError::invalid(name, window, "Kaiser(beta) with a finite beta >= 0.0")),
+        WindowKind::Tukey(alpha) if !(0.0..=1.0).contains(&alpha) => Err(NessError::invalid(name, window, "Tukey(alpha) with alpha between 0.0 and 1.0")),
+        _ => Ok(()),
+    }
+}
+
+//the first and last frame of the region process_file stretches, in a file of file_len frames
+pub(crate) fn validate_region(config: &NessConfig, sample_rate: u32, file_len: usize) -> Result<(usize, usize), NessError> {
+    let start = config.region_start.map_or(0, |start| start.frames(sample_rate));
+    let end = config.region_end.map_or(file_len, |end| end.frames(sample_rate).min(file_len));
+    if start >= file_len {
+        return Err(NessError::invalid("region_start", config.region_start, format!("a point before the end of the file ({} samples)", file_len)));
+    }
+    if end <= start {
+        return Err(NessError::invalid("region_end", config.region_end, format!("a point after region_start ({} samples)", start)));
+    }
+    Ok((start, end))
+}
+
+//the stretch that makes target_len frames of output from input_len frames
+pub(crate) fn target_dur_mult(config: &NessConfig, input_len: usize, target_len: usize) -> Result<f64, NessError> {
+    if target_len < input_len {
+        return Err(NessError::invalid("target_duration", config.target_duration, format!("at least the length of the input ({} samples)", input_len)));
+    }
+    Ok(target_len as f64 / input_len as f64)
+}
+
+//checks the slices a NessStruct is built with against what the sample rate allows
+//every window that will be used has to fit inside max_win_size
+pub(crate) fn validate_layout(config: &NessConfig, layout: &[SliceLayout], sample_rate: u32, num_slices: usize, num_channels: usize) -> Result<(), NessError> {
+    if num_channels == 0 {
+        return Err(NessError::invalid("num_channels", num_channels, ">= 1"));
+    }
+    if num_slices > layout.len() {
+        return Err(NessError::invalid("num_slices", num_slices, format!("1..={} (the slices a sample rate of {} allows)", layout.len(), sample_rate)));
+    }
+    if let Some(band_windows) = &config.band_windows {
+        if band_windows.len() != num_slices {
+            return Err(NessError::invalid("band_windows", band_windows, format!("one window for each of the {} slices in use", num_slices)));
+        }
+    }
+    if let Some(band_dur_mults) = &config.band_dur_mults {
+        if band_dur_mults.len() != num_slices {
+            return Err(NessError::invalid("band_dur_mults", band_dur_mults, format!("one stretch for each of the {} slices in use", num_slices)));
+        }
+    }
+    if let Some(band_gains) = &config.band_gains {
+        if band_gains.len() != num_slices {
+            return Err(NessError::invalid("band_gains", band_gains, format!("one gain for each of the {} slices in use", num_slices)));
+        }
+    }
+    if let Some(band) = config.muted_bands.iter().find(|band| **band >= num_slices) {
+        return Err(NessError::invalid("muted_bands", band, format!("0..{} (the slices in use)", num_slices)));
+    }
+    if let Some(band) = config.soloed_bands.iter().find(|band| **band >= num_slices) {
+        return Err(NessError::invalid("soloed_bands", band, format!("0..{} (the slices in use)", num_slices)));
+    }
+    let max_win_size = chunk_size(config, layout);
+    //windows given in the config make the chunk, so they always fit
+    //single slice mode uses the paulstretch window, otherwise the largest slice window
+    let largest_win = if let Some(win_lens) = &config.win_lens {
+        if win_lens.len() != num_slices {
+            return Err(NessError::invalid("win_lens", win_lens, format!("one window for each of the {} slices in use", num_slices)));
+        }
+        max_win_size
+    } else if num_slices == 1 {
+        config.paulstretch_window.win_len_at(sample_rate)
+    } else {
+        layout[num_slices - 1].win_len
+    };
+    if largest_win > max_win_size {
+        return Err(NessError::invalid(
+            "paulstretch_window",
+            config.paulstretch_window,
+            format!("a window of at most {} (the window of the lowest crossover band)", max_win_size),
+        ));
+    }
+    let frames = max_win_size / (largest_win / 2);
+    if config.win_size_divisor > frames {
+        return Err(NessError::invalid("win_size_divisor", config.win_size_divisor, format!("1..={}", frames)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::config_layout;
+    use crate::{PaulstretchWindow, StretchCurve};
+
+    fn assert_invalid(result: Result<(), NessError>, name: &str, allowed: &str) {
+        match result {
+            Err(NessError::InvalidParameter { name: got_name, allowed: got_allowed, .. }) => {
+                assert_eq!((got_name, &got_allowed[..]), (name, allowed));
+            }
+            other => panic!("expected {} to be invalid, got {:?}", name, other),
+        }
+    }
+
+    #[test]
+    fn every_setting_is_checked_against_its_range() {
+        let base = NessConfig::new(2.0);
+        let cases = vec![
+            (NessConfig::new(0.5), "dur_mult", "a finite number >= 1.0"),
+            (NessConfig::new(f64::NAN), "dur_mult", "a finite number >= 1.0"),
+            (base.clone().stretch_curve(StretchCurve::breakpoints(vec![])), "stretch_curve", "at least one breakpoint"),
+            (base.clone().stretch_curve(StretchCurve::breakpoints(vec![(0.0, 0.5)])), "stretch_curve", "breakpoints with finite times and stretches >= 1.0"),
+            (base.clone().stretch_curve(StretchCurve::breakpoints(vec![(1.0, 2.0), (1.0, 3.0)])), "stretch_curve", "breakpoints in order of time"),
+            (base.clone().band_dur_mults(vec![2.0, 0.5]), "band_dur_mults", "finite stretches >= 1.0"),
+            (base.clone().extreme(ExtremeMode::CorrelationSearch(0)), "extreme", "CorrelationSearch(1..=100)"),
+            (base.clone().extreme(ExtremeMode::CorrelationSearch(101)), "extreme", "CorrelationSearch(1..=100)"),
+            (base.clone().num_slices(0), "num_slices", ">= 1"),
+            (base.clone().crossovers(vec![]), "crossovers", "at least one frequency (num_slices(1) is the unsliced paulstretch)"),
+            (base.clone().crossovers(vec![1000.0, 0.0]), "crossovers", "frequencies above 0Hz"),
+            (base.clone().crossovers(vec![1000.0, 500.0, 1000.0]), "crossovers", "every crossover only once"),
+            (base.clone().win_lens(vec![]), "win_lens", "at least one window"),
+            (base.clone().win_lens(vec![1024, 1023]), "win_lens", "even windows of 16 to 1048576 samples"),
+            (base.clone().win_lens(vec![8]), "win_lens", "even windows of 16 to 1048576 samples"),
+            (base.clone().band_split(BandSplitMode::Overlapping(-1.0)), "band_split", "Overlapping(octaves) with a finite number of octaves >= 0.0"),
+            (base.clone().band_split(BandSplitMode::Layered(f64::INFINITY)), "band_split", "Layered(gain_db) with a finite gain"),
+            (base.clone().band_gains(vec![0.0, f64::NAN]), "band_gains", "finite gains in dB"),
+            (base.clone().crossover_order(0.0), "crossover_order", "a finite number above 0.0"),
+            (base.clone().window(WindowKind::Kaiser(-1.0)), "window", "Kaiser(beta) with a finite beta >= 0.0"),
+            (base.clone().window(WindowKind::Tukey(1.5)), "window", "Tukey(alpha) with alpha between 0.0 and 1.0"),
+            (base.clone().band_windows(vec![]), "band_windows", "at least one window"),
+            (base.clone().band_windows(vec![WindowKind::Tukey(-0.5)]), "band_windows", "Tukey(alpha) with alpha between 0.0 and 1.0"),
+            (base.clone().region_start(InputPosition::Seconds(-1.0)), "region_start", "a finite number of seconds >= 0.0"),
+            (base.clone().region_end(InputPosition::Seconds(f64::NAN)), "region_end", "a finite number of seconds >= 0.0"),
+            (base.clone().tail(OutputLength::Seconds(-1.0)), "tail", "a finite number of seconds >= 0.0"),
+            (base.clone().target_duration(OutputLength::Seconds(0.0)), "target_duration", "a finite number of seconds above 0.0"),
+            (base.clone().target_duration(OutputLength::Samples(0)), "target_duration", ">= 1 sample"),
+            (base.clone().target_duration(OutputLength::Seconds(10.0)).num_output_blocks(2), "num_output_blocks", "not set when target_duration is set"),
+            (base.clone().target_duration(OutputLength::Seconds(10.0)).tail(OutputLength::Seconds(1.0)), "tail", "not set when target_duration is set"),
+            (base.clone().win_size_divisor(0), "win_size_divisor", ">= 1"),
+            (base.clone().num_output_blocks(0), "num_output_blocks", ">= 1"),
+            (base.clone().num_threads(0), "num_threads", ">= 1"),
+        ];
+        for (config, name, allowed) in cases {
+            assert_invalid(validate_config(&config), name, allowed);
+        }
+        assert!(validate_config(&base).is_ok());
+    }
+
+    //checks a config against the default layout at 44.1K, which has 9 slices with a largest window of 65536
+    fn check_layout(config: &NessConfig, num_channels: usize) -> Result<(), NessError> {
+        let layout = config_layout(config, 44100)?;
+        let num_slices = config.num_slices.unwrap_or(layout.len());
+        validate_layout(config, &layout, 44100, num_slices, num_channels)
+    }
+
+    #[test]
+    fn every_setting_is_checked_against_the_layout() {
+        let base = NessConfig::new(2.0);
+        assert_invalid(check_layout(&base, 0), "num_channels", ">= 1");
+        let cases = vec![
+            (base.clone().num_slices(10), "num_slices", "1..=9 (the slices a sample rate of 44100 allows)"),
+            (base.clone().band_windows(vec![WindowKind::Hann]), "band_windows", "one window for each of the 9 slices in use"),
+            (base.clone().band_dur_mults(vec![2.0]), "band_dur_mults", "one stretch for each of the 9 slices in use"),
+            (base.clone().band_gains(vec![0.0]), "band_gains", "one gain for each of the 9 slices in use"),
+            (base.clone().muted_bands(vec![9]), "muted_bands", "0..9 (the slices in use)"),
+            (base.clone().soloed_bands(vec![3, 9]), "soloed_bands", "0..9 (the slices in use)"),
+            (base.clone().win_lens(vec![1024]), "win_lens", "one window for each of the 9 slices in use"),
+            (
+                base.clone().crossovers(vec![5000.0]).num_slices(1).paulstretch_window(PaulstretchWindow::Small),
+                "paulstretch_window",
+                "a window of at most 1024 (the window of the lowest crossover band)",
+            ),
+            (base.clone().win_size_divisor(3), "win_size_divisor", "1..=2"),
+        ];
+        for (config, name, allowed) in cases {
+            assert_invalid(check_layout(&config, 1), name, allowed);
+        }
+        assert!(check_layout(&base, 2).is_ok());
+    }
+}
@@ -0,0 +1,74 @@
+use std::f64::consts::PI;
+
+//the window each frame of a slice is multiplied by before its fft
+//they trade the width of the main lobe against how far the sidelobes are down, which is most audible on tonal material
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum WindowKind {
+    //the paulstretch window, (1 - x^2)^1.25
+    #[default]
+    Paul,
+    Hann,
+    Sine,
+    //the 4 term blackman-harris, sidelobes 92dB down
+    BlackmanHarris,
+    //the higher the beta the lower the sidelobes and the wider the main lobe - 8.6 is close to blackman-harris
+    Kaiser(f64),
+    //flat in the middle with cosine tapers over alpha of the window - 0 is rectangular and 1 is hann
+    Tukey(f64),
+}
+
+impl WindowKind {
+    //makes the window, symmetric over len points
+    pub(crate) fn make(self, len: usize) -> Vec<f64> {
+        match self {
+            WindowKind::Paul => make_paul_window(len),
+            WindowKind::Hann => make_window(len, |x| 0.5 - 0.5 * (2.0 * PI * x).cos()),
+            WindowKind::Sine => make_window(len, |x| (PI * x).sin()),
+            WindowKind::BlackmanHarris => make_window(len, |x| {
+                0.35875 - 0.48829 * (2.0 * PI * x).cos() + 0.14128 * (4.0 * PI * x).cos() - 0.01168 * (6.0 * PI * x).cos()
+            }),
+            WindowKind::Kaiser(beta) => make_window(len, |x| {
+                let value = 2.0 * x - 1.0;
+                bessel_i0(beta * (1.0 - value * value).sqrt()) / bessel_i0(beta)
+            }),
+            WindowKind::Tukey(alpha) => make_window(len, |x| {
+                let edge = x.min(1.0 - x);
+                if edge >= alpha / 2.0 {
+                    1.0
+                } else {
+                    0.5 - 0.5 * (2.0 * PI * edge / alpha).cos()
+                }
+            }),
+        }
+    }
+}
+
+//fills a window from a function of the position, 0 at the first point and 1 at the last
+fn make_window(len: usize, window: impl Fn(f64) -> f64) -> Vec<f64> {
+    (0..len).map(|i| window(i as f64 / (len as f64 - 1.0))).collect()
+}
+
+//the paul stretch window is used on input - might a well be a sine or hann window
+#[allow(clippy::needless_range_loop, clippy::needless_return)]
+pub(crate) fn make_paul_window(len: usize) -> Vec<f64> {
+    let mut part = vec![0.0; len];
+    for i in 0..len {
+        let value = i as f64 / (len as f64 - 1.0) * 2.0 - 1.0;
+        let value = f64::powf(1.0 - (f64::powf(value, 2.0)), 1.25);
+        part[i] = value;
+    }
+    return part;
+}
+
+//the zeroth order modified bessel function, from its power series
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let mut k = 1.0;
+    while term > sum * 1e-16 {
+        term *= (x / (2.0 * k)) * (x / (2.0 * k));
+        sum += term;
+        k += 1.0;
+    }
+    sum
+}